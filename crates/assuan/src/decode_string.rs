@@ -25,6 +25,20 @@ pub fn decode(s: &str) -> Result<String, DecodingErr> {
     Ok(new)
 }
 
+/// Percent-escapes the characters that can't be sent as-is in an assuan line.
+pub fn encode(s: &str) -> String {
+    let mut new = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        match c {
+            '%' | '\r' | '\n' => new.push_str(&format!("%{:02X}", c as u32)),
+            c => new.push(c),
+        }
+    }
+
+    new
+}
+
 pub struct Decoder<'a>(std::str::Chars<'a>);
 
 impl Iterator for Decoder<'_> {
@@ -70,6 +84,18 @@ mod test {
         assert_eq!(decode(candidate).unwrap(), "my 100% percent");
     }
 
+    #[test]
+    fn encode_special_characters() {
+        let candidate = "100% sure\r\n";
+        assert_eq!(encode(candidate), "100%25 sure%0D%0A");
+    }
+
+    #[test]
+    fn encode_then_decode() {
+        let candidate = "my 100% secret\npassphrase";
+        assert_eq!(decode(&encode(candidate)).unwrap(), candidate);
+    }
+
     #[test]
     fn decode_noop() {
         let candidate = "hello world";
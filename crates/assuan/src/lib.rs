@@ -4,7 +4,8 @@
 
 use std::time::Duration;
 
-use decode_string::decode;
+use decode_string::{decode, encode};
+use tracing::warn;
 
 mod decode_string;
 
@@ -18,6 +19,8 @@ pub enum ParseErr {
     InvalidDuration(String),
     UnknownOption(String),
     BadEncoding(String),
+    InvalidKeyInfo(String),
+    InvalidOwner(String),
 }
 
 impl std::error::Error for ParseErr {}
@@ -33,15 +36,148 @@ impl std::fmt::Display for ParseErr {
             ParseErr::InvalidDuration(s) => write!(f, "invalid duration, error converting `{s}`"),
             ParseErr::UnknownOption(s) => write!(f, "unknown OPTION named `{s}`"),
             ParseErr::BadEncoding(s) => write!(f, "bad encoding for `{s}`"),
+            ParseErr::InvalidKeyInfo(s) => write!(f, "invalid key info `{s}`"),
+            ParseErr::InvalidOwner(s) => write!(f, "invalid owner `{s}`"),
         }
     }
 }
 
+/// Error source used by libgpg-error for the pinentry.
+const GPG_ERR_SOURCE_PINENTRY: u32 = 5;
+
+/// Errors reported back to the agent, the values match the ones from libgpg-error.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ErrorCode {
+    Timeout,
+    Canceled,
+    NotConfirmed,
+}
+
+impl ErrorCode {
+    #[must_use]
+    pub fn code(self) -> u32 {
+        let code = match self {
+            ErrorCode::Timeout => 62,
+            ErrorCode::Canceled => 99,
+            ErrorCode::NotConfirmed => 114,
+        };
+        (GPG_ERR_SOURCE_PINENTRY << 24) | code
+    }
+
+    #[must_use]
+    pub fn description(self) -> &'static str {
+        match self {
+            ErrorCode::Timeout => "Timeout",
+            ErrorCode::Canceled => "Operation cancelled",
+            ErrorCode::NotConfirmed => "Not confirmed",
+        }
+    }
+}
+
+/// Questions asked to the agent while a dialog is displayed, the agent answers with `D` lines
+/// followed by `END`.
+#[derive(PartialEq)]
+pub enum Inquiry {
+    /// Asks for the quality of the passphrase, as a percentage.
+    Quality(String),
+    /// Asks if the passphrase respects the constraints, the agent answers with the reason
+    /// of the rejection or nothing when the passphrase is accepted.
+    CheckPin(String),
+    /// Asks the agent to generate a passphrase.
+    GenPin,
+}
+
+impl Inquiry {
+    fn keyword(&self) -> &'static str {
+        match self {
+            Inquiry::Quality(_) => "QUALITY",
+            Inquiry::CheckPin(_) => "CHECKPIN",
+            Inquiry::GenPin => "GENPIN",
+        }
+    }
+}
+
+/// How the agent caches the passphrase of the key.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CacheMode {
+    Normal,
+    User,
+    Ssh,
+}
+
+/// Key concerned by the dialog, sent by the agent as `<mode>/<keygrip>`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct KeyInfo {
+    pub cache_mode: CacheMode,
+    pub keygrip: String,
+}
+
+impl TryFrom<&str> for KeyInfo {
+    type Error = ParseErr;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let invalid = || ParseErr::InvalidKeyInfo(value.to_owned());
+
+        let (mode, keygrip) = value.split_once('/').ok_or_else(invalid)?;
+        let cache_mode = match mode {
+            "n" => CacheMode::Normal,
+            "u" => CacheMode::User,
+            "s" => CacheMode::Ssh,
+            _ => return Err(invalid()),
+        };
+
+        // A keygrip is the hex encoded SHA-1 of the public key.
+        if keygrip.len() != 40 || !keygrip.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+
+        Ok(KeyInfo {
+            cache_mode,
+            keygrip: keygrip.to_ascii_uppercase(),
+        })
+    }
+}
+
+/// Process that asked the agent for the passphrase, sent as `<pid>/<uid> <hostname>`. Older
+/// agents only send the pid and the hostname.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Owner {
+    pub pid: u32,
+    pub uid: Option<u32>,
+    pub hostname: Option<String>,
+}
+
+impl TryFrom<&str> for Owner {
+    type Error = ParseErr;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let invalid = |_| ParseErr::InvalidOwner(value.to_owned());
+
+        let (ids, hostname) = match value.split_once(' ') {
+            Some((ids, hostname)) => (ids, Some(hostname.trim().to_owned())),
+            None => (value, None),
+        };
+        let (pid, uid) = match ids.split_once('/') {
+            Some((pid, uid)) => (pid, Some(uid.parse::<u32>().map_err(invalid)?)),
+            None => (ids, None),
+        };
+
+        Ok(Owner {
+            pid: pid.parse::<u32>().map_err(invalid)?,
+            uid,
+            hostname: hostname.filter(|h| !h.is_empty()),
+        })
+    }
+}
+
 #[derive(PartialEq)]
 pub enum Response {
     Ok,
     OkHello,
     Data(String),
+    Status(String),
+    Inquire(Inquiry),
+    Err(ErrorCode),
 }
 
 impl core::fmt::Debug for Response {
@@ -50,6 +186,9 @@ impl core::fmt::Debug for Response {
             Response::Ok => write!(f, "OK"),
             Response::OkHello => write!(f, "OK Please go ahead"),
             Response::Data(_) => write!(f, "D <SECURE>"),
+            Response::Status(s) => write!(f, "S {s}"),
+            Response::Inquire(i) => write!(f, "INQUIRE {} <SECURE>", i.keyword()),
+            Response::Err(e) => write!(f, "ERR {} {}", e.code(), e.description()),
         }
     }
 }
@@ -60,6 +199,9 @@ impl std::fmt::Display for Response {
             Response::Ok => write!(f, "OK"),
             Response::OkHello => write!(f, "OK Please go ahead"),
             Response::Data(_) => write!(f, "D <SECURE>"),
+            Response::Status(s) => write!(f, "S {s}"),
+            Response::Inquire(i) => write!(f, "INQUIRE {} <SECURE>", i.keyword()),
+            Response::Err(e) => write!(f, "ERR {} {}", e.code(), e.description()),
         }
     }
 }
@@ -70,21 +212,30 @@ impl Response {
         match self {
             Response::Ok => "OK".to_string(),
             Response::OkHello => "OK Please go ahead".to_string(),
-            Response::Data(d) => format!("D {d}"), // This is need to be escaped
+            Response::Data(d) => format!("D {}", encode(d)),
+            Response::Status(s) => format!("S {s}"),
+            Response::Inquire(i @ (Inquiry::Quality(pin) | Inquiry::CheckPin(pin))) => {
+                format!("INQUIRE {} {}", i.keyword(), encode(pin))
+            }
+            Response::Inquire(i @ Inquiry::GenPin) => format!("INQUIRE {}", i.keyword()),
+            Response::Err(e) => format!("ERR {} {} <Pinentry>", e.code(), e.description()),
         }
     }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Command {
-    // TODO(ph)
-    // Need to investigate theses commands
-    // show dialog
-    // Config, 	// Ask for confirmation
-    // Message, // Show a message
     Reset,
     Quit,
     GetPin,
+    Confirm {
+        one_button: bool,
+    },
+    Message,
+    /// Answer of the agent to an inquiry.
+    Data(String),
+    End,
+    Cancel,
     Bye,
     GetInfo(String),
     SetTitle(String),
@@ -96,13 +247,22 @@ pub enum Command {
     SetCancel(String),
     SetNotOk(String),
     SetError(String),
-    SetRepeat,
-    SetQualityBar,
+    SetRepeat(String),
+    SetRepeatError(String),
+    SetQualityBar(String),
     SetQualityBarTT(String),
     Option(OptionArgs),
-    SetGenPin,
+    SetGenPin(String),
     SetGenPinTT(String),
-    SetKeyInfo(String),
+    /// The key of the following dialogs, `None` when the agent sends `--clear`, an empty
+    /// serial number or a key info that can't be understood.
+    SetKeyInfo(Option<KeyInfo>),
+    /// The PIN of a smartcard is requested, the agent sends a key info starting with `--`
+    /// followed by the serial number of the card.
+    SetCardKeyInfo(String),
+    /// Removes the passphrase of the key from the external cache, `None` when the key info
+    /// can't be understood.
+    ClearPassphrase(Option<KeyInfo>),
 }
 
 impl TryFrom<String> for Command {
@@ -133,24 +293,40 @@ impl TryFrom<String> for Command {
                 Ok(Command::SetTimeOut(d))
             }
             "GETPIN" => Ok(Command::GetPin),
+            "MESSAGE" => Ok(Command::Message),
+            "D" => Ok(Command::Data(decode_message(remainder)?)),
+            "END" => Ok(Command::End),
+            "CAN" => Ok(Command::Cancel),
+            "CONFIRM" => Ok(Command::Confirm {
+                one_button: remainder.split_whitespace().any(|a| a == "--one-button"),
+            }),
             "GETINFO" => Ok(Command::GetInfo(remainder.to_owned())),
             "QUIT" => Ok(Command::Quit),
             "BYE" => Ok(Command::Bye),
             "RESET" => Ok(Command::Reset),
-            "SETTITLE" => Ok(Command::SetTitle(remainder.to_owned())),
+            "SETTITLE" => Ok(Command::SetTitle(decode_message(remainder)?)),
             "SETDESC" => Ok(Command::SetDesc(decode_message(remainder)?)),
             "SETPROMPT" => Ok(Command::SetPrompt(remainder.to_owned())),
             "SETOK" => Ok(Command::SetOk(remainder.to_owned())),
             "SETCANCEL" => Ok(Command::SetCancel(remainder.to_owned())),
             "SETNOTOK" => Ok(Command::SetNotOk(remainder.to_owned())),
-            "SETERROR" => Ok(Command::SetError(remainder.to_owned())),
-            "SETREPEAT" => Ok(Command::SetRepeat),
-            "SETQUALITYBAR" => Ok(Command::SetQualityBar),
+            "SETERROR" => Ok(Command::SetError(decode_message(remainder)?)),
+            "SETREPEAT" => Ok(Command::SetRepeat(remainder.to_owned())),
+            "SETREPEATERROR" => Ok(Command::SetRepeatError(remainder.to_owned())),
+            "SETQUALITYBAR" => Ok(Command::SetQualityBar(remainder.to_owned())),
             "SETQUALITYBAR_TT" => Ok(Command::SetQualityBarTT(remainder.to_owned())),
-            "SETGENPIN" => Ok(Command::SetGenPin),
+            "SETGENPIN" => Ok(Command::SetGenPin(remainder.to_owned())),
             "SETGENPIN_TT" => Ok(Command::SetGenPinTT(remainder.to_owned())),
             "OPTION" => Ok(Command::Option(OptionArgs::try_from(remainder)?)),
-            "SETKEYINFO" => Ok(Command::SetKeyInfo(remainder.to_owned())),
+            "SETKEYINFO" => match remainder {
+                // A card without a serial number can't be told apart from the others.
+                "--clear" | "--" => Ok(Command::SetKeyInfo(None)),
+                _ if remainder.starts_with("--") => {
+                    Ok(Command::SetCardKeyInfo(remainder[2..].to_owned()))
+                }
+                _ => Ok(Command::SetKeyInfo(lenient_key_info(remainder))),
+            },
+            "CLEARPASSPHRASE" => Ok(Command::ClearPassphrase(lenient_key_info(remainder))),
             _ => Err(ParseErr::UnknownCommand(value)),
         }
     }
@@ -187,7 +363,8 @@ pub enum OptionArgs {
     DefaultTTHide(String),
     DefaultCapsHint(String),
     TouchFile(String),
-    Owner(String),
+    /// `None` when the owner sent by the agent can't be understood.
+    Owner(Option<Owner>),
     AllowExternalPasswordCache,
     NoGrab,
     Grab,
@@ -224,9 +401,13 @@ impl TryFrom<&str> for OptionArgs {
             ("default-tt-hide", _) => Ok(OptionArgs::DefaultTTHide(args.to_owned())), //test
             ("default-capshint", _) => Ok(OptionArgs::DefaultCapsHint(args.to_owned())), //test
             ("touch-file", _) => Ok(OptionArgs::TouchFile(args.to_owned())),         //test
-            ("owner", _) => Ok(OptionArgs::Owner(args.to_owned())),                  //test
-            ("no-grab", _) => Ok(OptionArgs::NoGrab),                                //test
-            ("grab", _) => Ok(OptionArgs::Grab),                                     //test
+            ("owner", _) => Ok(OptionArgs::Owner(
+                Owner::try_from(args)
+                    .inspect_err(|err| warn!("ignoring the owner: {err}"))
+                    .ok(),
+            )),
+            ("no-grab", _) => Ok(OptionArgs::NoGrab), //test
+            ("grab", _) => Ok(OptionArgs::Grab),      //test
             ("default-prompt", _) => Ok(OptionArgs::DefaultPrompt(args.to_owned())),
             ("allow-external-password-cache", "") => Ok(OptionArgs::AllowExternalPasswordCache),
             (_, _) => Err(ParseErr::UnknownOption(value.to_owned())),
@@ -234,6 +415,14 @@ impl TryFrom<&str> for OptionArgs {
     }
 }
 
+// An unexpected key info only disables the features tied to the key, it must not end the
+// conversation with the agent.
+fn lenient_key_info(s: &str) -> Option<KeyInfo> {
+    KeyInfo::try_from(s)
+        .inspect_err(|err| warn!("ignoring the key info: {err}"))
+        .ok()
+}
+
 fn decode_message(s: &str) -> Result<String, ParseErr> {
     decode(s).map_err(|_| ParseErr::BadEncoding(s.to_owned()))
 }
@@ -277,6 +466,14 @@ mod test {
         )
     }
 
+    #[test]
+    fn parse_encoded_set_title() {
+        assert_eq!(
+            Command::SetTitle("Passphrase 100%".to_string()),
+            Command::try_from("SETTITLE Passphrase 100%25").unwrap()
+        )
+    }
+
     #[test]
     fn parse_set_timeout() {
         assert_eq!(
@@ -332,19 +529,54 @@ mod test {
         )
     }
 
+    #[test]
+    fn parse_encoded_set_error() {
+        assert_eq!(
+            Command::SetError("Bad Passphrase (try 2 of 3)".to_string()),
+            Command::try_from("SETERROR Bad%20Passphrase (try 2 of 3)").unwrap()
+        )
+    }
+
     #[test]
     fn parse_set_repeat() {
-        assert_eq!(Command::SetRepeat, Command::try_from("SETREPEAT").unwrap())
+        assert_eq!(
+            Command::SetRepeat("".to_string()),
+            Command::try_from("SETREPEAT").unwrap()
+        )
+    }
+
+    #[test]
+    fn parse_set_repeat_with_label() {
+        assert_eq!(
+            Command::SetRepeat("Repeat:".to_string()),
+            Command::try_from("SETREPEAT Repeat:").unwrap()
+        )
+    }
+
+    #[test]
+    fn parse_set_repeat_error() {
+        assert_eq!(
+            Command::SetRepeatError("does not match".to_string()),
+            Command::try_from("SETREPEATERROR does not match").unwrap()
+        )
     }
 
     #[test]
     fn parse_set_quality_bar() {
         assert_eq!(
-            Command::SetQualityBar,
+            Command::SetQualityBar("".to_string()),
             Command::try_from("SETQUALITYBAR").unwrap()
         )
     }
 
+    #[test]
+    fn parse_set_quality_bar_with_label() {
+        assert_eq!(
+            Command::SetQualityBar("Quality:".to_string()),
+            Command::try_from("SETQUALITYBAR Quality:").unwrap()
+        )
+    }
+
     #[test]
     fn parse_set_quality_bar_tt() {
         assert_eq!(
@@ -524,11 +756,39 @@ mod test {
     #[test]
     fn parse_option_owner() {
         assert_eq!(
-            Command::Option(OptionArgs::Owner("29982/1000 babayaga".to_string())),
+            Command::Option(OptionArgs::Owner(Some(Owner {
+                pid: 29982,
+                uid: Some(1000),
+                hostname: Some("babayaga".to_string()),
+            }))),
             Command::try_from("OPTION owner=29982/1000 babayaga").unwrap()
         )
     }
 
+    #[test]
+    fn parse_option_owner_without_uid() {
+        assert_eq!(
+            Command::Option(OptionArgs::Owner(Some(Owner {
+                pid: 29982,
+                uid: None,
+                hostname: Some("babayaga".to_string()),
+            }))),
+            Command::try_from("OPTION owner=29982 babayaga").unwrap()
+        )
+    }
+
+    #[test]
+    fn parse_invalid_option_owner() {
+        assert_eq!(
+            Err(ParseErr::InvalidOwner("me/1000 babayaga".to_string())),
+            Owner::try_from("me/1000 babayaga")
+        );
+        assert_eq!(
+            Command::Option(OptionArgs::Owner(None)),
+            Command::try_from("OPTION owner=me/1000 babayaga").unwrap()
+        )
+    }
+
     #[test]
     fn parse_option_no_grab() {
         assert_eq!(
@@ -550,11 +810,130 @@ mod test {
         assert_eq!(Command::GetPin, Command::try_from("GETPIN").unwrap())
     }
 
+    #[test]
+    fn parse_message() {
+        assert_eq!(Command::Message, Command::try_from("MESSAGE").unwrap())
+    }
+
+    #[test]
+    fn parse_data() {
+        assert_eq!(
+            Command::Data("42%".to_string()),
+            Command::try_from("D 42%25").unwrap()
+        )
+    }
+
+    #[test]
+    fn parse_end() {
+        assert_eq!(Command::End, Command::try_from("END").unwrap())
+    }
+
+    #[test]
+    fn parse_cancel() {
+        assert_eq!(Command::Cancel, Command::try_from("CAN").unwrap())
+    }
+
+    #[test]
+    fn parse_confirm() {
+        assert_eq!(
+            Command::Confirm { one_button: false },
+            Command::try_from("CONFIRM").unwrap()
+        )
+    }
+
+    #[test]
+    fn parse_confirm_one_button() {
+        assert_eq!(
+            Command::Confirm { one_button: true },
+            Command::try_from("CONFIRM --one-button").unwrap()
+        )
+    }
+
     #[test]
     fn parse_set_key_info() {
         assert_eq!(
-            Command::SetKeyInfo("hello".to_string()),
-            Command::try_from("SETKEYINFO hello").unwrap()
+            Command::SetKeyInfo(Some(KeyInfo {
+                cache_mode: CacheMode::Normal,
+                keygrip: "0123456789ABCDEF0123456789ABCDEF01234567".to_string(),
+            })),
+            Command::try_from("SETKEYINFO n/0123456789abcdef0123456789ABCDEF01234567").unwrap()
+        )
+    }
+
+    #[test]
+    fn parse_set_key_info_ssh() {
+        assert_eq!(
+            Command::SetKeyInfo(Some(KeyInfo {
+                cache_mode: CacheMode::Ssh,
+                keygrip: "0123456789ABCDEF0123456789ABCDEF01234567".to_string(),
+            })),
+            Command::try_from("SETKEYINFO s/0123456789ABCDEF0123456789ABCDEF01234567").unwrap()
+        )
+    }
+
+    #[test]
+    fn parse_set_key_info_clear() {
+        assert_eq!(
+            Command::SetKeyInfo(None),
+            Command::try_from("SETKEYINFO --clear").unwrap()
+        )
+    }
+
+    #[test]
+    fn parse_set_card_key_info() {
+        assert_eq!(
+            Command::SetCardKeyInfo("D2760001240103040006123456780000".to_string()),
+            Command::try_from("SETKEYINFO --D2760001240103040006123456780000").unwrap()
+        )
+    }
+
+    #[test]
+    fn parse_set_card_key_info_without_serial() {
+        assert_eq!(
+            Command::SetKeyInfo(None),
+            Command::try_from("SETKEYINFO --").unwrap()
+        )
+    }
+
+    #[test]
+    fn parse_invalid_key_info() {
+        assert_eq!(
+            Err(ParseErr::InvalidKeyInfo("hello".to_string())),
+            KeyInfo::try_from("hello")
+        );
+        assert_eq!(
+            Err(ParseErr::InvalidKeyInfo(
+                "x/0123456789ABCDEF0123456789ABCDEF01234567".to_string()
+            )),
+            KeyInfo::try_from("x/0123456789ABCDEF0123456789ABCDEF01234567")
+        );
+        assert_eq!(
+            Err(ParseErr::InvalidKeyInfo("n/not-a-keygrip".to_string())),
+            KeyInfo::try_from("n/not-a-keygrip")
+        );
+    }
+
+    #[test]
+    fn parse_invalid_key_info_is_ignored() {
+        assert_eq!(
+            Command::SetKeyInfo(None),
+            Command::try_from("SETKEYINFO n/not-a-keygrip").unwrap()
+        );
+        assert_eq!(
+            Command::ClearPassphrase(None),
+            Command::try_from("CLEARPASSPHRASE hello").unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_clear_passphrase() {
+        assert_eq!(
+            Command::ClearPassphrase(Some(KeyInfo {
+                cache_mode: CacheMode::Normal,
+                keygrip: "0123456789ABCDEF0123456789ABCDEF01234567".to_string(),
+            })),
+            Command::try_from("CLEARPASSPHRASE n/0123456789ABCDEF0123456789ABCDEF01234567")
+                .unwrap()
         )
     }
 
@@ -570,7 +949,18 @@ mod test {
 
     #[test]
     fn parse_set_set_gen_pin() {
-        assert_eq!(Command::SetGenPin, Command::try_from("SETGENPIN").unwrap())
+        assert_eq!(
+            Command::SetGenPin("".to_string()),
+            Command::try_from("SETGENPIN").unwrap()
+        )
+    }
+
+    #[test]
+    fn parse_set_set_gen_pin_with_label() {
+        assert_eq!(
+            Command::SetGenPin("Suggest".to_string()),
+            Command::try_from("SETGENPIN Suggest").unwrap()
+        )
     }
 
     #[test]
@@ -588,4 +978,68 @@ mod test {
             Command::try_from("SETDESC Hello%25world").unwrap()
         )
     }
+
+    #[test]
+    fn response_data_is_escaped() {
+        assert_eq!(
+            "D 100%25%0Asecret",
+            Response::Data("100%\nsecret".to_string()).to_pinentry()
+        )
+    }
+
+    #[test]
+    fn response_inquire_quality() {
+        assert_eq!(
+            "INQUIRE QUALITY my%25pin",
+            Response::Inquire(Inquiry::Quality("my%pin".to_string())).to_pinentry()
+        )
+    }
+
+    #[test]
+    fn response_inquire_checkpin() {
+        assert_eq!(
+            "INQUIRE CHECKPIN secret",
+            Response::Inquire(Inquiry::CheckPin("secret".to_string())).to_pinentry()
+        )
+    }
+
+    #[test]
+    fn response_inquire_genpin() {
+        assert_eq!(
+            "INQUIRE GENPIN",
+            Response::Inquire(Inquiry::GenPin).to_pinentry()
+        )
+    }
+
+    #[test]
+    fn response_status() {
+        assert_eq!(
+            "S PIN_REPEATED",
+            Response::Status("PIN_REPEATED".to_string()).to_pinentry()
+        )
+    }
+
+    #[test]
+    fn response_timeout_error() {
+        assert_eq!(
+            "ERR 83886142 Timeout <Pinentry>",
+            Response::Err(ErrorCode::Timeout).to_pinentry()
+        )
+    }
+
+    #[test]
+    fn response_not_confirmed_error() {
+        assert_eq!(
+            "ERR 83886194 Not confirmed <Pinentry>",
+            Response::Err(ErrorCode::NotConfirmed).to_pinentry()
+        )
+    }
+
+    #[test]
+    fn response_canceled_error() {
+        assert_eq!(
+            "ERR 83886179 Operation cancelled <Pinentry>",
+            Response::Err(ErrorCode::Canceled).to_pinentry()
+        )
+    }
 }
@@ -10,3 +10,30 @@ view = View
 welcome = Welcome to COSMIC! ✨
 page-id = Page { $num }
 git-description = Git commit {$hash} on {$date}
+timeout-countdown = Closing in {$seconds}s
+
+label-prompt = PIN:
+label-repeat = Repeat:
+label-repeat-error = Passphrases don't match
+label-quality-bar = Quality:
+label-caps-hint = Caps Lock is on
+label-confirm-visibility = Do you really want to make your passphrase visible on the screen?
+tooltip-visibility = Make passphrase visible
+tooltip-hide = Hide passphrase
+label-password-manager = _Save in password manager
+requested-by = Requested by {$name}
+foreign-host-warning = This request comes from the agent of the remote host {$host}, only continue if you expect it.
+foreign-user-warning = This request comes from another user (uid {$uid}), only continue if you expect it.
+button-continue = C_ontinue
+key-label = Key: {$label}
+key-token = Key on the smartcard {$serial}
+pin-length = { $count ->
+    [one] 1 digit
+   *[other] {$count} digits
+}
+pin-non-digits = PINs usually only contain digits
+button-ok = OK
+button-cancel = Cancel
+button-yes = Yes
+button-no = No
+button-gen-pin = Generate
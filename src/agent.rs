@@ -0,0 +1,15 @@
+// SPDX-FileCopyrightText: 2025 Pier-Hugues Pellerin <ph@heykimo.com>
+//
+// SPDX-License-Identifier: MIT
+
+use crate::error::ZuulErr;
+use assuan::Response;
+use std::io::Write;
+
+/// Sends the response to the agent on stdout, whatever the front end.
+pub fn reply(response: &Response) -> Result<(), ZuulErr> {
+    let mut stdout = std::io::stdout().lock();
+    writeln!(stdout, "{}", response.to_pinentry()).map_err(|e| ZuulErr::Output(e.kind()))?;
+    stdout.flush().map_err(|e| ZuulErr::Output(e.kind()))?;
+    Ok(())
+}
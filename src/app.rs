@@ -4,43 +4,78 @@
 
 // SPDX-License-Identfier: {{ license }}
 
+use crate::agent;
+use crate::caps_lock;
+use crate::config::Config;
 use crate::error::ZuulErr;
-use crate::form::Form;
+use crate::fl;
+use crate::icons::{self, HEADER_ICON_SIZE, REQUESTER_ICON_SIZE};
+use crate::key_label::{self, KeyLabel};
+use crate::layer_shell;
+use crate::mnemonic::Mnemonic;
+use crate::requester::{self, Foreign, Requester};
+use crate::secret_store::KernelKeyring;
+use crate::session::{Dialog, Disconnect, Effect, Input, Mode, Session, SystemClock};
 use crate::subscription::{Event, read_external_commands_input};
-use assuan::Response;
+use crate::touch::touch;
+use assuan::Owner;
 use cosmic::cosmic_theme::Spacing;
 use cosmic::iced::alignment::{Horizontal, Vertical};
+use cosmic::iced::event::Status;
 use cosmic::iced::id::Id;
 use cosmic::iced::keyboard::{self, Key, key::Named};
 use cosmic::iced::platform_specific::shell::commands::layer_surface::{
     KeyboardInteractivity, Layer, get_layer_surface,
 };
-use cosmic::iced::{Border, Color, Length, Shadow, Subscription, window};
+use cosmic::iced::{Border, Color, Length, Padding, Shadow, Size, Subscription, time, window};
 use cosmic::iced_runtime::core::layout::Limits;
 use cosmic::iced_runtime::core::window::Id as SurfaceId;
 use cosmic::iced_runtime::platform_specific::wayland::layer_surface::SctkLayerSurfaceSettings;
-use cosmic::iced_widget::row;
+use cosmic::iced_widget::text::Span;
+use cosmic::iced_widget::{Row, progress_bar, rich_text, row, span};
 use cosmic::iced_winit::commands::layer_surface::destroy_layer_surface;
 use cosmic::prelude::*;
 use cosmic::theme::{self, Container};
 use cosmic::widget::{Column, container, id_container, text_input, vertical_space};
 use cosmic::widget::{autosize, horizontal_space};
-use cosmic::widget::{button, text};
-use std::io::BufWriter;
-use std::io::Write;
+use cosmic::widget::{button, checkbox, icon, text, tooltip};
+use std::borrow::Cow;
+use std::f32::consts::TAU;
+use std::path::PathBuf;
 use std::sync::LazyLock;
-use tracing::error;
+use tracing::{error, warn};
 
 static AUTOSIZE_ID: LazyLock<Id> = LazyLock::new(|| Id::new("autosize"));
 static MAIN_ID: LazyLock<Id> = LazyLock::new(|| Id::new("main"));
 static INPUT_PASSPHRASE_ID: LazyLock<Id> = LazyLock::new(|| Id::new("input_passphrase"));
+static INPUT_REPEAT_ID: LazyLock<Id> = LazyLock::new(|| Id::new("input_repeat"));
+
+// Keys of the on-screen keypad are large enough to be used on a touch screen.
+const KEYPAD_KEY_WIDTH: f32 = 72.0;
+const KEYPAD_KEY_HEIGHT: f32 = 48.0;
+
+static DIALOG_ICON: LazyLock<Option<PathBuf>> =
+    LazyLock::new(|| icons::lookup("dialog-password", HEADER_ICON_SIZE));
+
+// Horizontal shake of the dialog when the agent asks again after an error.
+const SHAKE_AMPLITUDE: f32 = 8.0;
+const SHAKE_COUNT: f32 = 3.0;
 
 /// The application model stores app-specific state used to describe its interface and
 /// drive its logic.
 pub struct Zuul {
     core: cosmic::Core,
     window_id: window::Id,
-    state: State,
+    session: Session,
+    caps_lock: bool,
+    // Application that asked for the dialog currently displayed, along with its icon.
+    requester: Option<(Requester, Option<PathBuf>)>,
+    // Label of the key the dialog is about.
+    key_label: Option<KeyLabel>,
+    // Dialogs are displayed above fullscreen windows.
+    overlay: bool,
+    // The dialog is a layer surface, or a regular window when the compositor can't display one.
+    layer_shell: bool,
 }
 
 /// Messages emitted by the application and its widgets.
@@ -49,38 +84,59 @@ pub enum Message {
     External(Event),
     ButtonOkPressed,
     ButtonCancelPressed,
+    ButtonNotOkPressed,
     OnPassphraseChange(String),
     OnPassphraseSubmit(String),
+    OnRepeatChange(String),
+    OnRepeatSubmit(String),
     Exit,
     Result(Result<(), ZuulErr>),
     TogglePassphraseVisibility,
+    ConfirmVisibility(bool),
+    SavePassphraseToggled(bool),
+    AcknowledgeForeign,
+    GeneratePassphrase,
+    KeypadDigit(char),
+    KeypadBackspace,
+    KeypadEnter,
+    /// A letter was pressed with Alt.
+    Mnemonic(String),
+    CapsLockToggled,
+    /// The state of Caps Lock guessed from a typed letter.
+    CapsLock(bool),
+    /// The lookups started when the dialog was shown are done.
+    Identified(Box<Identity>),
+    Tick,
+    Quit(i32),
 }
 
-#[derive(Clone)]
-enum State {
-    WaitingForm(WaitingState),
-    Display(DisplayState),
-    WaitingValidation,
+/// Requester and key label of a dialog. Reading `/proc` and the files of GnuPG can be slow,
+/// they are looked up on another thread.
+#[derive(Debug, Clone)]
+pub struct Identity {
+    owner: Option<Owner>,
+    keygrip: Option<String>,
+    requester: Option<(Requester, Option<PathBuf>)>,
+    key_label: Option<KeyLabel>,
 }
 
-impl std::fmt::Display for State {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            State::WaitingForm(_) => write!(f, "State::WaitingForm"),
-            State::Display(_) => write!(f, "State::Display"),
-            State::WaitingValidation => write!(f, "State::WaitingValidation"),
+impl Identity {
+    fn lookup(owner: Option<Owner>, keygrip: Option<String>) -> Identity {
+        let requester = owner.as_ref().and_then(resolve_requester);
+        let key_label = keygrip.as_deref().and_then(key_label::lookup);
+        Identity {
+            owner,
+            keygrip,
+            requester,
+            key_label,
         }
     }
-}
-
-#[derive(Default, Clone)]
-struct WaitingState {}
 
-#[derive(Default, Clone)]
-struct DisplayState {
-    form: Form,
-    passphrase: String,
-    passphrase_is_visible: bool,
+    // A dialog may have been replaced while the lookups were running.
+    fn is_for(&self, dialog: &Dialog) -> bool {
+        self.owner.as_ref() == dialog.form().owner()
+            && self.keygrip.as_deref() == dialog.form().key_info().map(|k| k.keygrip.as_str())
+    }
 }
 
 impl cosmic::Application for Zuul {
@@ -99,13 +155,25 @@ impl cosmic::Application for Zuul {
     }
 
     fn init(core: cosmic::Core, _flags: Self::Flags) -> (Self, cosmic::app::Task<Self::Message>) {
-        let app = Zuul {
+        let config = Config::load(Self::APP_ID);
+        let mut app = Zuul {
             window_id: SurfaceId::unique(),
-            state: State::WaitingForm(WaitingState::default()),
+            session: Session::new(SystemClock, KernelKeyring)
+                .with_visibility_confirmation(config.confirm_visibility)
+                .with_foreign_confirmation(config.confirm_foreign_requests)
+                .with_keypad(config.keypad())
+                .with_grab(config.grab),
+            caps_lock: false,
+            requester: None,
+            key_label: None,
+            overlay: config.overlay,
+            layer_shell: config.layer_shell.unwrap_or_else(layer_shell::is_available),
             core,
         };
 
-        (app, Task::none())
+        let hello = app.session.hello();
+        let task = app.perform(hello);
+        (app, task)
     }
 
     fn view(&self) -> Element<Self::Message> {
@@ -115,63 +183,129 @@ impl cosmic::Application for Zuul {
     fn view_window(&self, _id: SurfaceId) -> Element<Self::Message> {
         let Spacing { space_s, .. } = theme::active().cosmic().spacing;
 
-        match &self.state {
-            State::Display(state) => {
-                let prompt = text(state.form.prompt());
-
-                let pin = text_input::secure_input(
-                    "",
-                    state.passphrase.clone(),
-                    Some(Message::TogglePassphraseVisibility),
-                    !state.passphrase_is_visible,
-                )
-                .id(INPUT_PASSPHRASE_ID.clone())
-                .editing(true)
-                .always_active()
-                .on_input(Message::OnPassphraseChange)
-                .on_submit(Message::OnPassphraseSubmit);
-
-                let description = state
-                    .form
-                    .description()
-                    .map(|d| text::caption(d).align_y(Vertical::Center));
-
-                let actions = container(
-                    row![
-                        horizontal_space().width(Length::Fill),
-                        button::standard(state.form.button_cancel())
-                            .on_press(Message::ButtonCancelPressed),
-                        button::suggested(state.form.button_ok())
-                            .on_press(Message::ButtonOkPressed),
-                    ]
-                    .spacing(space_s),
-                )
-                .align_x(Horizontal::Right);
+        match self.session.dialog() {
+            Some(dialog) => {
+                let header = dialog.form().title().map(|title| {
+                    let dialog_icon = DIALOG_ICON
+                        .clone()
+                        .map(|path| icon(icon::from_path(path)).size(HEADER_ICON_SIZE));
+
+                    row![]
+                        .push_maybe(dialog_icon)
+                        .push(text::title4(title))
+                        .align_y(Vertical::Center)
+                        .spacing(space_s)
+                });
+
+                let requester = self.requester.as_ref().map(|(requester, icon_path)| {
+                    let details = std::iter::once(requester.executable.display().to_string())
+                        .chain(requester.command_line.iter().skip(1).cloned())
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    let app_icon = icon_path
+                        .clone()
+                        .map(|path| icon(icon::from_path(path)).size(REQUESTER_ICON_SIZE));
+
+                    tooltip(
+                        row![]
+                            .push_maybe(app_icon)
+                            .push(text::caption(fl!(
+                                "requested-by",
+                                name = requester.name.as_str()
+                            )))
+                            .align_y(Vertical::Center)
+                            .spacing(space_s),
+                        text::caption(details),
+                        tooltip::Position::Bottom,
+                    )
+                });
+
+                let foreign = dialog
+                    .foreign()
+                    .map(|foreign| view_foreign(dialog, foreign, space_s));
+
+                let error = dialog
+                    .form()
+                    .error()
+                    .map(|e| text(e).class(theme::Text::Color(destructive_color())));
+
+                let body = match dialog.mode() {
+                    Mode::Passphrase => Some(view_passphrase(dialog, self.caps_lock, space_s)),
+                    Mode::Confirm { .. } | Mode::Message => None,
+                };
+
+                // Without any input, the description is the main content of the dialog.
+                let description = dialog.form().description().map(|d| match dialog.mode() {
+                    Mode::Passphrase => text::caption(d).align_y(Vertical::Center),
+                    Mode::Confirm { .. } | Mode::Message => text(d).align_y(Vertical::Center),
+                });
+
+                let key_label = self.key_label.as_ref().map(|label| {
+                    text::caption(match label {
+                        KeyLabel::Label(label) | KeyLabel::Comment(label) => {
+                            fl!("key-label", label = label.as_str())
+                        }
+                        KeyLabel::Token(serial) => fl!("key-token", serial = serial.as_str()),
+                    })
+                });
+
+                let countdown = self.session.countdown().map(|(remaining, timeout)| {
+                    Column::new()
+                        .push(progress_bar(
+                            0.0..=timeout.as_secs_f32(),
+                            remaining.as_secs_f32(),
+                        ))
+                        .push(text::caption(fl!(
+                            "timeout-countdown",
+                            seconds = remaining.as_secs()
+                        )))
+                        .spacing(space_s)
+                });
 
                 let content = Column::new()
-                    .push(prompt)
-                    .push(pin)
+                    .push_maybe(header)
+                    .push_maybe(foreign)
+                    .push_maybe(requester)
+                    .push_maybe(error)
+                    .push_maybe(body)
                     .push_maybe(description)
+                    .push_maybe(key_label)
+                    .push_maybe(countdown)
                     .push(vertical_space().height(Length::Fixed(16.)))
-                    .push(actions)
+                    .push(view_actions(dialog, space_s))
                     .spacing(space_s);
 
+                let retry_cue = self.session.retry_cue();
+
                 let window = container(id_container(content, MAIN_ID.clone()))
                     .width(Length::Shrink)
                     .height(Length::Shrink)
-                    .class(Container::Custom(Box::new(|theme| container::Style {
+                    .class(Container::Custom(Box::new(move |theme| container::Style {
                         text_color: Some(theme.cosmic().on_bg_color().into()),
                         icon_color: Some(theme.cosmic().on_bg_color().into()),
                         background: Some(Color::from(theme.cosmic().background.base).into()),
                         border: Border {
                             radius: theme.cosmic().corner_radii.radius_m.into(),
                             width: 1.0,
-                            color: theme.cosmic().bg_divider().into(),
+                            color: match retry_cue {
+                                Some(_) => theme.cosmic().destructive_color().into(),
+                                None => theme.cosmic().bg_divider().into(),
+                            },
                         },
                         shadow: Shadow::default(),
                     })))
                     .padding(space_s);
 
+                let shake = retry_cue.map_or(0.0, |progress| {
+                    SHAKE_AMPLITUDE * (progress * SHAKE_COUNT * TAU).sin() * (1.0 - progress)
+                });
+                let window = container(window).padding(Padding {
+                    top: 0.0,
+                    bottom: 0.0,
+                    left: SHAKE_AMPLITUDE + shake,
+                    right: SHAKE_AMPLITUDE - shake,
+                });
+
                 autosize::autosize(window, AUTOSIZE_ID.clone())
                     .auto_height(true)
                     .min_width(300.)
@@ -179,89 +313,174 @@ impl cosmic::Application for Zuul {
                     .max_height(1920.)
                     .into()
             }
-            State::WaitingForm(_) | State::WaitingValidation => row![].into(),
+            None => row![].into(),
         }
     }
 
     fn update(&mut self, message: Self::Message) -> cosmic::app::Task<Self::Message> {
-        match &mut self.state {
-            State::WaitingForm(_) | State::WaitingValidation => match message {
-                Message::External(Event::Bye) => Self::exit(),
-                Message::External(Event::Form(form)) => {
-                    return self.transition(State::Display(DisplayState {
-                        form,
-                        ..Default::default()
-                    }));
-                }
-                _ => {}
-            },
-            State::Display(s) => match message {
-                Message::Exit | Message::ButtonCancelPressed => Self::exit(),
-                Message::ButtonOkPressed => {
-                    return self.transition(State::WaitingValidation);
-                }
-                Message::OnPassphraseChange(passphrase) => {
-                    s.passphrase = passphrase;
-                }
-                Message::OnPassphraseSubmit(passphrase) => {
-                    s.passphrase = passphrase;
-                    return self.transition(State::WaitingValidation);
+        let effects = match message {
+            Message::External(Event::Command(command)) => self.session.command(command),
+            Message::External(Event::Closed) => self.session.disconnect(Disconnect::Eof),
+            Message::OnPassphraseSubmit(_)
+                if self
+                    .session
+                    .dialog()
+                    .is_some_and(|d| d.form().repeat().is_some()) =>
+            {
+                return text_input::focus(INPUT_REPEAT_ID.clone());
+            }
+            Message::ButtonOkPressed
+            | Message::OnPassphraseSubmit(_)
+            | Message::OnRepeatSubmit(_) => self.session.input(Input::Ok),
+            Message::OnRepeatChange(repeat) => self.session.input(Input::RepeatChange(repeat)),
+            Message::ButtonNotOkPressed => self.session.input(Input::NotOk),
+            Message::Exit | Message::ButtonCancelPressed => self.session.input(Input::Cancel),
+            Message::OnPassphraseChange(passphrase) => {
+                self.session.input(Input::PassphraseChange(passphrase))
+            }
+            Message::TogglePassphraseVisibility => {
+                self.session.input(Input::TogglePassphraseVisibility)
+            }
+            Message::ConfirmVisibility(confirmed) => {
+                self.session.input(Input::ConfirmVisibility(confirmed))
+            }
+            Message::AcknowledgeForeign => {
+                let effects = self.session.input(Input::AcknowledgeForeign);
+                return self.perform(effects).chain(self.focus_passphrase());
+            }
+            Message::SavePassphraseToggled(save) => self.session.input(Input::SavePassphrase(save)),
+            Message::GeneratePassphrase => self.session.input(Input::GeneratePassphrase),
+            Message::KeypadDigit(digit) => self.session.input(Input::KeypadDigit(digit)),
+            Message::KeypadBackspace => self.session.input(Input::KeypadBackspace),
+            Message::KeypadEnter => self.session.input(Input::KeypadEnter),
+            Message::Mnemonic(key) => {
+                match self
+                    .session
+                    .dialog()
+                    .and_then(|dialog| mnemonic_action(dialog, &key))
+                {
+                    Some(message) => return self.update(message),
+                    None => Vec::new(),
                 }
-                Message::Result(r) => match r {
-                    Ok(()) => Self::exit(),
-                    Err(err) => {
-                        error!("Error: {err}");
-                        std::process::exit(exitcode::DATAERR);
+            }
+            Message::CapsLockToggled => {
+                self.caps_lock = caps_lock::is_on().unwrap_or(!self.caps_lock);
+                Vec::new()
+            }
+            Message::CapsLock(active) => {
+                self.caps_lock = active;
+                Vec::new()
+            }
+            Message::Identified(identity) => {
+                if self
+                    .session
+                    .dialog()
+                    .is_some_and(|dialog| identity.is_for(dialog))
+                {
+                    // A card dialog has no keygrip, its label is already displayed.
+                    self.requester = identity.requester;
+                    if identity.keygrip.is_some() {
+                        self.key_label = identity.key_label;
                     }
-                },
-                Message::TogglePassphraseVisibility => {
-                    s.passphrase_is_visible = !s.passphrase_is_visible;
                 }
-                Message::External(_) => {}
+                Vec::new()
+            }
+            Message::Tick => self.session.input(Input::Tick),
+            Message::Quit(code) => std::process::exit(code),
+            Message::Result(r) => match r {
+                Ok(()) => Vec::new(),
+                Err(err) => {
+                    error!("Error: {err}");
+                    self.session.disconnect(Disconnect::BrokenInput)
+                }
             },
-        }
-        Task::none()
+        };
+
+        self.perform(effects)
+    }
+
+    // Closing the window, with Alt+F4 for instance, cancels the dialog.
+    fn on_close_requested(&self, _id: window::Id) -> Option<Message> {
+        Some(Message::Exit)
     }
 
     fn subscription(&self) -> Subscription<Message> {
-        Subscription::batch(vec![
-            subscribe_to_commands(),
-            subscribe_to_specific_events(),
-        ])
+        let mut subscriptions = vec![subscribe_to_commands(), subscribe_to_specific_events()];
+
+        if let Some(interval) = self.session.tick_interval() {
+            subscriptions.push(time::every(interval).map(|_| Message::Tick));
+        }
+
+        Subscription::batch(subscriptions)
     }
 }
 
 impl Zuul {
-    fn transition(&mut self, new_state: State) -> cosmic::app::Task<Message> {
-        match (self.state.clone(), new_state.clone()) {
-            (State::WaitingForm(..) | State::WaitingValidation, State::Display(..)) => {
-                self.state = new_state;
-                self.show()
-                    .chain(text_input::focus(INPUT_PASSPHRASE_ID.clone()))
-            }
-            (State::Display(s), State::WaitingValidation) => {
-                self.state = new_state;
-                Task::batch(vec![self.hide(), send_passphrase(s.passphrase.clone())])
-            }
-            _ => {
-                error!(
-                    "Error: This is a bug, unexpected transition from `{}` to `{new_state}`",
-                    self.state
-                );
-                std::process::exit(exitcode::DATAERR);
-            }
+    /// Executes the effects requested by the session, in order.
+    fn perform(&mut self, effects: Vec<Effect>) -> cosmic::app::Task<Message> {
+        let mut task = Task::none();
+        // Runs beside the effects, a chained task would wait for the lookups to finish.
+        let mut lookups = Task::none();
+
+        for effect in effects {
+            task = match effect {
+                Effect::Reply(response) => {
+                    if let Err(err) = agent::reply(&response) {
+                        error!("Error: {err}");
+                        let effects = self.session.disconnect(Disconnect::BrokenOutput);
+                        return task.chain(self.perform(effects));
+                    }
+                    task
+                }
+                Effect::Show => {
+                    // Caps Lock may have been turned on before the dialog was opened.
+                    self.caps_lock = caps_lock::is_on().unwrap_or(self.caps_lock);
+                    self.requester = None;
+                    self.key_label = self
+                        .session
+                        .dialog()
+                        .and_then(|dialog| dialog.form().card())
+                        .map(|serial| KeyLabel::Token(serial.to_owned()));
+                    lookups = self.identify();
+                    task.chain(self.show()).chain(self.focus_passphrase())
+                }
+                Effect::Hide => task.chain(self.hide()),
+                Effect::TouchFile(path) => {
+                    if let Err(err) = touch(&path) {
+                        warn!("Can't touch `{}`: {err}", path.display());
+                    }
+                    task
+                }
+                // Exiting is delayed until the surface is destroyed.
+                Effect::Exit(code) => {
+                    task.chain(Task::done(cosmic::action::app(Message::Quit(code))))
+                }
+            };
         }
-    }
 
-    fn exit() {
-        std::process::exit(exitcode::OK);
+        Task::batch(vec![task, lookups])
     }
 
-    fn show(&self) -> cosmic::app::Task<Message> {
+    fn show(&mut self) -> cosmic::app::Task<Message> {
+        if !self.layer_shell {
+            return self.show_window();
+        }
+
+        let keyboard_interactivity = if self.session.dialog().is_none_or(Dialog::grab) {
+            KeyboardInteractivity::Exclusive
+        } else {
+            KeyboardInteractivity::OnDemand
+        };
+        let layer = if self.overlay {
+            Layer::Overlay
+        } else {
+            Layer::Top
+        };
+
         Task::batch(vec![get_layer_surface(SctkLayerSurfaceSettings {
             id: self.window_id,
-            keyboard_interactivity: KeyboardInteractivity::Exclusive,
-            layer: Layer::Top,
+            keyboard_interactivity,
+            layer,
             namespace: "zuul".into(),
             size: None,
             size_limits: Limits::NONE.min_width(1.0).min_height(1.0).max_width(600.0),
@@ -270,17 +489,106 @@ impl Zuul {
         })])
     }
 
+    // Without layer-shell, the dialog is an undecorated window centered above the others. The
+    // keyboard can't be grabbed. The content isn't measured before the window is opened, so
+    // the user can resize it within the width allowed to the layer surface when a long
+    // description doesn't fit.
+    fn show_window(&mut self) -> cosmic::app::Task<Message> {
+        let (id, open) = window::open(window::Settings {
+            size: Size::new(450.0, 320.0),
+            min_size: Some(Size::new(300.0, 200.0)),
+            max_size: Some(Size::new(600.0, 900.0)),
+            position: window::Position::Centered,
+            resizable: true,
+            decorations: false,
+            transparent: true,
+            level: window::Level::AlwaysOnTop,
+            exit_on_close_request: false,
+            ..Default::default()
+        });
+        self.window_id = id;
+        open.discard()
+    }
+
+    // Only the passphrase dialog has an input, CONFIRM and MESSAGE keep the focus on their
+    // buttons.
+    fn focus_passphrase(&self) -> cosmic::app::Task<Message> {
+        match self.session.dialog().map(Dialog::mode) {
+            Some(Mode::Passphrase) => text_input::focus(INPUT_PASSPHRASE_ID.clone()),
+            _ => Task::none(),
+        }
+    }
+
+    // The result is only used if the dialog is still displayed, see `Message::Identified`.
+    fn identify(&self) -> cosmic::app::Task<Message> {
+        let Some(dialog) = self.session.dialog() else {
+            return Task::none();
+        };
+        let owner = dialog.form().owner().cloned();
+        let keygrip = dialog.form().key_info().map(|k| k.keygrip.clone());
+        if owner.is_none() && keygrip.is_none() {
+            return Task::none();
+        }
+
+        // The executor runs on the UI thread.
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        std::thread::spawn(move || {
+            let _ = sender.send(Identity::lookup(owner, keygrip));
+        });
+        Task::perform(receiver, |identity| {
+            cosmic::action::app(match identity {
+                Ok(identity) => Message::Identified(Box::new(identity)),
+                // The lookups panicked, nothing to display.
+                Err(_) => Message::Result(Ok(())),
+            })
+        })
+    }
+
     fn hide(&self) -> cosmic::app::Task<Message> {
-        destroy_layer_surface(self.window_id)
+        if self.layer_shell {
+            destroy_layer_surface(self.window_id)
+        } else {
+            window::close(self.window_id)
+        }
     }
 }
 
+// Unknown requesters, like a process already gone, are simply not displayed.
+fn resolve_requester(owner: &Owner) -> Option<(Requester, Option<PathBuf>)> {
+    let requester = requester::resolve(owner)?;
+    let icon = requester
+        .icon
+        .as_deref()
+        .and_then(|icon| icons::lookup_entry_icon(icon, REQUESTER_ICON_SIZE));
+    Some((requester, icon))
+}
+
 fn subscribe_to_specific_events() -> Subscription<Message> {
-    cosmic::iced::event::listen_raw(|e, _status, _id| match e {
+    cosmic::iced::event::listen_raw(|e, status, _id| match e {
         cosmic::iced::Event::Keyboard(keyboard::Event::KeyPressed {
             key: Key::Named(Named::Escape),
             ..
         }) => Some(Message::Exit),
+        // A key captured by a widget, like a character typed in the passphrase with AltGr,
+        // never triggers a button.
+        cosmic::iced::Event::Keyboard(keyboard::Event::KeyPressed {
+            key: Key::Character(c),
+            modifiers,
+            ..
+        }) if modifiers.alt() && status == Status::Ignored => {
+            Some(Message::Mnemonic(c.to_string()))
+        }
+        // The modifiers don't include Caps Lock, its LED is read once the key is released and
+        // the lock applied.
+        cosmic::iced::Event::Keyboard(keyboard::Event::KeyReleased {
+            key: Key::Named(Named::CapsLock),
+            ..
+        }) => Some(Message::CapsLockToggled),
+        cosmic::iced::Event::Keyboard(keyboard::Event::KeyPressed {
+            text: Some(text),
+            modifiers,
+            ..
+        }) => caps_lock_state(&text, modifiers.shift()).map(Message::CapsLock),
         _ => None,
     })
 }
@@ -296,20 +604,412 @@ pub fn subscribe_to_commands() -> Subscription<Message> {
     })
 }
 
-#[allow(clippy::unused_async)]
-async fn reply(responses: Vec<Response>) -> Result<(), ZuulErr> {
-    let mut stdout = std::io::stdout();
-    let mut w = BufWriter::new(&mut stdout);
-    for response in responses {
-        writeln!(w, "{}", response.to_pinentry()).map_err(|_| ZuulErr::Output)?;
+fn view_passphrase(dialog: &Dialog, caps_lock: bool, space_s: u16) -> Column<'_, Message> {
+    let prompt = text(dialog.form().prompt());
+
+    let pin = text_input::secure_input(
+        "",
+        dialog.displayed_passphrase(),
+        None,
+        !dialog.passphrase_is_visible(),
+    )
+    .id(INPUT_PASSPHRASE_ID.clone())
+    .editing(true)
+    .always_active()
+    .on_input(Message::OnPassphraseChange)
+    .on_submit(Message::OnPassphraseSubmit);
+
+    let generate = dialog.form().gen_pin().map(|label| {
+        let button =
+            mnemonic_button(&label, theme::Button::Standard).on_press(Message::GeneratePassphrase);
+        match dialog.form().gen_pin_tt() {
+            Some(tt) => tooltip(button, text(tt), tooltip::Position::Bottom).into(),
+            None => Element::from(button),
+        }
+    });
+
+    // The toggle of the secure input can't have a tooltip, zuul uses its own.
+    let (visibility_icon, visibility_tt) = if dialog.passphrase_is_visible() {
+        ("view-conceal-symbolic", dialog.form().tooltip_hide())
+    } else {
+        ("view-reveal-symbolic", dialog.form().tooltip_visibility())
+    };
+    let visibility = tooltip(
+        button::icon(icon::from_name(visibility_icon))
+            .on_press(Message::TogglePassphraseVisibility),
+        text(visibility_tt),
+        tooltip::Position::Bottom,
+    );
+
+    let pin = row![pin, visibility]
+        .push_maybe(generate)
+        .align_y(Vertical::Center)
+        .spacing(space_s);
+
+    let repeat = dialog.form().repeat().map(|label| {
+        Column::new()
+            .push(text(label))
+            .push(
+                text_input::secure_input(
+                    "",
+                    dialog.displayed_repeat(),
+                    None,
+                    !dialog.passphrase_is_visible(),
+                )
+                .id(INPUT_REPEAT_ID.clone())
+                .on_input(Message::OnRepeatChange)
+                .on_submit(Message::OnRepeatSubmit),
+            )
+            .spacing(space_s)
+    });
+
+    let repeat_error = dialog
+        .repeat_error()
+        .map(|e| text::caption(e).class(theme::Text::Color(destructive_color())));
+
+    let quality = dialog
+        .form()
+        .quality_bar()
+        .map(|label| view_quality(dialog, label, space_s));
+
+    let confirm_visibility = dialog.confirming_visibility().then(|| {
+        Column::new()
+            .push(text::caption(dialog.form().confirm_visibility()))
+            .push(
+                row![
+                    horizontal_space().width(Length::Fill),
+                    mnemonic_button(&dialog.form().no(), theme::Button::Standard)
+                        .on_press(Message::ConfirmVisibility(false)),
+                    mnemonic_button(&dialog.form().yes(), theme::Button::Destructive)
+                        .on_press(Message::ConfirmVisibility(true)),
+                ]
+                .spacing(space_s),
+            )
+            .spacing(space_s)
+    });
+
+    // Some cards accept letters, the user is only warned about them.
+    let pin_length = dialog.is_pin().then(|| {
+        if dialog.pin_has_non_digits() {
+            text::caption(fl!("pin-non-digits"))
+        } else {
+            text::caption(fl!(
+                "pin-length",
+                count = dialog.passphrase().chars().count()
+            ))
+        }
+    });
+
+    let keypad = dialog
+        .keypad()
+        .map(|layout| view_keypad(dialog, layout, space_s));
+
+    let password_manager = dialog.form().password_manager().map(|label| {
+        checkbox(
+            Mnemonic::parse(&label).text().to_owned(),
+            dialog.save_passphrase(),
+        )
+        .on_toggle(Message::SavePassphraseToggled)
+    });
+
+    let caps_hint = caps_lock.then(|| text::caption(dialog.form().caps_hint()));
+
+    let formatted_passphrase_hint = dialog.formatted_passphrase_hint().map(text::caption);
+
+    let constraints_error = dialog
+        .constraints_error()
+        .map(|e| text::caption(e).class(theme::Text::Color(destructive_color())));
+
+    // The long hint is displayed on demand, when hovering the short one.
+    let constraints_hint = match (
+        dialog.form().constraints_hint_short(),
+        dialog.form().constraints_hint_long(),
+    ) {
+        (Some(short), Some(long)) => {
+            Some(tooltip(text::caption(short), text(long), tooltip::Position::Bottom).into())
+        }
+        (Some(hint), None) | (None, Some(hint)) => Some(text::caption(hint).into()),
+        (None, None) => None,
+    };
+
+    Column::new()
+        .push(prompt)
+        .push(pin)
+        .push_maybe(pin_length)
+        .push_maybe(confirm_visibility)
+        .push_maybe(caps_hint)
+        .push_maybe(formatted_passphrase_hint)
+        .push_maybe(constraints_error)
+        .push_maybe::<Element<'_, Message>>(constraints_hint)
+        .push_maybe(quality)
+        .push_maybe(repeat)
+        .push_maybe(repeat_error)
+        .push_maybe(keypad)
+        .push_maybe(password_manager)
+        .spacing(space_s)
+}
+
+/// Numeric keypad typing the PIN, the last row holds the backspace and enter keys.
+fn view_keypad<'a>(dialog: &Dialog, layout: &[char; 10], space_s: u16) -> Element<'a, Message> {
+    let key = |content: Element<'a, Message>, message| {
+        button::custom(
+            container(content)
+                .width(Length::Fill)
+                .height(Length::Fill)
+                .align_x(Horizontal::Center)
+                .align_y(Vertical::Center),
+        )
+        .width(Length::Fixed(KEYPAD_KEY_WIDTH))
+        .height(Length::Fixed(KEYPAD_KEY_HEIGHT))
+        .class(theme::Button::Standard)
+        .on_press(message)
+    };
+    let digit = |d: char| key(text::title4(d.to_string()).into(), Message::KeypadDigit(d));
+
+    // The enter key moves to the repetition before accepting the PIN.
+    let enter_icon = if dialog.form().repeat().is_some() && !dialog.keypad_on_repeat() {
+        "go-next-symbolic"
+    } else {
+        "object-select-symbolic"
+    };
+
+    let mut keys = Column::new().spacing(space_s);
+    for digits in layout[..9].chunks(3) {
+        keys =
+            keys.push(Row::with_children(digits.iter().map(|d| digit(*d).into())).spacing(space_s));
+    }
+    keys = keys.push(
+        row![
+            key(
+                icon::from_name("edit-clear-symbolic").icon().into(),
+                Message::KeypadBackspace
+            ),
+            digit(layout[9]),
+            key(
+                icon::from_name(enter_icon).icon().into(),
+                Message::KeypadEnter
+            ),
+        ]
+        .spacing(space_s),
+    );
+
+    container(keys)
+        .width(Length::Fill)
+        .align_x(Horizontal::Center)
+        .into()
+}
+
+fn view_quality<'a>(dialog: &'a Dialog, label: Cow<'a, str>, space_s: u16) -> Element<'a, Message> {
+    let quality = dialog.quality().unwrap_or_default();
+    let percent = quality.unsigned_abs();
+
+    let mut value = text::caption(format!("{percent}%"));
+    if quality < 0 {
+        value = value.class(theme::Text::Color(destructive_color()));
     }
 
-    w.flush().map_err(|_| ZuulErr::Output)?;
-    Ok(())
+    let meter = row![
+        text::caption(label),
+        progress_bar(0.0..=100.0, percent as f32),
+        value,
+    ]
+    .align_y(Vertical::Center)
+    .spacing(space_s);
+
+    match dialog.form().quality_bar_tt() {
+        Some(tt) => tooltip(meter, text(tt), tooltip::Position::Bottom).into(),
+        None => meter.into(),
+    }
 }
 
-fn send_passphrase(passphrase: String) -> cosmic::app::Task<Message> {
-    Task::perform(reply(vec![Response::Data(passphrase), Response::Ok]), |r| {
-        cosmic::action::app(Message::Result(r))
-    })
+/// Banner warning that the request doesn't come from the local user, like an agent forwarded
+/// over SSH.
+fn view_foreign<'a>(
+    dialog: &'a Dialog,
+    foreign: &'a Foreign,
+    space_s: u16,
+) -> Element<'a, Message> {
+    let warning = match foreign {
+        Foreign::Host(host) => fl!("foreign-host-warning", host = host.as_str()),
+        Foreign::User(uid) => fl!("foreign-user-warning", uid = uid),
+    };
+
+    let acknowledge = dialog.awaiting_acknowledgement().then(|| {
+        row![
+            horizontal_space().width(Length::Fill),
+            mnemonic_button(&fl!("button-continue"), theme::Button::Destructive)
+                .on_press(Message::AcknowledgeForeign),
+        ]
+    });
+
+    let banner = Column::new()
+        .push(
+            row![
+                icon::from_name("dialog-warning-symbolic")
+                    .size(HEADER_ICON_SIZE)
+                    .icon(),
+                text(warning).class(theme::Text::Color(destructive_color())),
+            ]
+            .align_y(Vertical::Center)
+            .spacing(space_s),
+        )
+        .push_maybe(acknowledge)
+        .spacing(space_s);
+
+    container(banner)
+        .class(Container::Custom(Box::new(|theme| container::Style {
+            border: Border {
+                radius: theme.cosmic().corner_radii.radius_s.into(),
+                width: 1.0,
+                color: theme.cosmic().destructive_color().into(),
+            },
+            ..Default::default()
+        })))
+        .padding(space_s)
+        .width(Length::Fill)
+        .into()
+}
+
+/// Button at the bottom of the dialog, its label may contain a mnemonic.
+struct Action<'a> {
+    label: Cow<'a, str>,
+    on_press: Option<Message>,
+    suggested: bool,
+}
+
+/// Buttons of the dialog, in the order they are displayed.
+fn actions(dialog: &Dialog) -> Vec<Action<'_>> {
+    let form = dialog.form();
+    let mut actions = Vec::new();
+
+    let one_button = matches!(
+        dialog.mode(),
+        Mode::Confirm { one_button: true } | Mode::Message
+    );
+    if !one_button {
+        actions.push(Action {
+            label: form.button_cancel(),
+            on_press: Some(Message::ButtonCancelPressed),
+            suggested: false,
+        });
+    }
+
+    if let (Mode::Confirm { one_button: false }, Some(label)) =
+        (dialog.mode(), form.button_not_ok())
+    {
+        actions.push(Action {
+            label,
+            on_press: Some(Message::ButtonNotOkPressed),
+            suggested: false,
+        });
+    }
+
+    actions.push(Action {
+        label: match dialog.mode() {
+            Mode::Confirm { .. } => form.button_yes(),
+            _ => form.button_ok(),
+        },
+        on_press: dialog
+            .passphrases_match()
+            .then_some(Message::ButtonOkPressed),
+        suggested: true,
+    });
+
+    actions
+}
+
+/// The message of the button whose mnemonic is the key pressed with Alt.
+fn mnemonic_action(dialog: &Dialog, key: &str) -> Option<Message> {
+    let form = dialog.form();
+    if dialog.awaiting_acknowledgement() {
+        return Mnemonic::parse(&fl!("button-continue"))
+            .is_triggered_by(key)
+            .then_some(Message::AcknowledgeForeign);
+    }
+
+    if dialog.confirming_visibility() {
+        return [
+            (form.yes(), Message::ConfirmVisibility(true)),
+            (form.no(), Message::ConfirmVisibility(false)),
+        ]
+        .into_iter()
+        .find(|(label, _)| Mnemonic::parse(label).is_triggered_by(key))
+        .map(|(_, message)| message);
+    }
+
+    let generate = form
+        .gen_pin()
+        .filter(|_| dialog.mode() == Mode::Passphrase)
+        .map(|label| (label, Some(Message::GeneratePassphrase)));
+
+    let password_manager = form
+        .password_manager()
+        .filter(|_| dialog.mode() == Mode::Passphrase)
+        .map(|label| {
+            let message = Message::SavePassphraseToggled(!dialog.save_passphrase());
+            (label, Some(message))
+        });
+
+    actions(dialog)
+        .into_iter()
+        .map(|action| (action.label, action.on_press))
+        .chain(generate)
+        .chain(password_manager)
+        .find(|(label, _)| Mnemonic::parse(label).is_triggered_by(key))
+        .and_then(|(_, on_press)| on_press)
+}
+
+fn view_actions(dialog: &Dialog, space_s: u16) -> Element<'_, Message> {
+    let buttons = actions(dialog).into_iter().map(|action| {
+        let class = if action.suggested {
+            theme::Button::Suggested
+        } else {
+            theme::Button::Standard
+        };
+        mnemonic_button(&action.label, class)
+            .on_press_maybe(action.on_press)
+            .into()
+    });
+
+    container(
+        row![horizontal_space().width(Length::Fill)]
+            .extend(buttons)
+            .spacing(space_s),
+    )
+    .align_x(Horizontal::Right)
+    .into()
+}
+
+/// Button displaying its label without the mnemonic markers, the accelerator is underlined.
+fn mnemonic_button<'a>(label: &str, class: theme::Button) -> button::Button<'a, Message> {
+    let Spacing {
+        space_xxs, space_s, ..
+    } = theme::active().cosmic().spacing;
+
+    let mnemonic = Mnemonic::parse(label);
+    let (before, accelerator, after) = mnemonic.split();
+    let mut spans: Vec<Span<'a>> = vec![span(before.to_owned())];
+    if let Some(accelerator) = accelerator {
+        spans.push(span(accelerator.to_owned()).underline(true));
+    }
+    spans.push(span(after.to_owned()));
+
+    button::custom(rich_text(spans))
+        .class(class)
+        .padding([space_xxs, space_s])
+}
+
+// A letter typed without Shift is uppercase when Caps Lock is active, and the opposite with
+// Shift. Anything else, like digits, doesn't tell.
+fn caps_lock_state(text: &str, shift: bool) -> Option<bool> {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_uppercase() => Some(!shift),
+        (Some(c), None) if c.is_lowercase() => Some(shift),
+        _ => None,
+    }
+}
+
+fn destructive_color() -> Color {
+    Color::from(theme::active().cosmic().destructive_color())
 }
@@ -0,0 +1,84 @@
+// SPDX-FileCopyrightText: 2025 Pier-Hugues Pellerin <ph@heykimo.com>
+//
+// SPDX-License-Identifier: MIT
+
+use std::path::Path;
+
+const LEDS: &str = "/sys/class/leds";
+
+/// State of Caps Lock read from the LEDs of the keyboards, `None` when none of them has one,
+/// like in a virtual machine or over a remote desktop.
+pub fn is_on() -> Option<bool> {
+    is_on_in(Path::new(LEDS))
+}
+
+// The lock is shared by every keyboard plugged, any lit LED is enough.
+fn is_on_in(leds: &Path) -> Option<bool> {
+    let mut state = None;
+
+    for entry in std::fs::read_dir(leds).ok()?.flatten() {
+        if !entry.file_name().to_string_lossy().ends_with("::capslock") {
+            continue;
+        }
+        let Ok(brightness) = std::fs::read_to_string(entry.path().join("brightness")) else {
+            continue;
+        };
+        let lit = brightness.trim().parse::<u32>().is_ok_and(|b| b > 0);
+        state = Some(state.unwrap_or(false) || lit);
+    }
+
+    state
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn leds(name: &str, brightness: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("zuul-{}-{name}", std::process::id()));
+        for (led, value) in brightness {
+            std::fs::create_dir_all(dir.join(led)).unwrap();
+            std::fs::write(dir.join(led).join("brightness"), value).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn caps_lock_led_is_read() {
+        let dir = leds(
+            "leds-on",
+            &[("input3::numlock", "0\n"), ("input3::capslock", "1\n")],
+        );
+        let on = is_on_in(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(Some(true), on);
+    }
+
+    #[test]
+    fn any_keyboard_with_the_led_lit() {
+        let dir = leds(
+            "leds-several",
+            &[("input3::capslock", "0\n"), ("input9::capslock", "1\n")],
+        );
+        let on = is_on_in(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(Some(true), on);
+    }
+
+    #[test]
+    fn caps_lock_led_is_off() {
+        let dir = leds("leds-off", &[("input3::capslock", "0\n")]);
+        let on = is_on_in(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(Some(false), on);
+    }
+
+    #[test]
+    fn no_caps_lock_led() {
+        let dir = leds("leds-none", &[("input3::numlock", "1\n")]);
+        let on = is_on_in(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(None, on);
+    }
+}
@@ -2,10 +2,68 @@
 //
 // SPDX-License-Identifier: MIT
 
+use crate::keypad::Keypad;
 use cosmic::cosmic_config::{self, CosmicConfigEntry, cosmic_config_derive::CosmicConfigEntry};
 
-#[derive(Debug, Default, Clone, CosmicConfigEntry, Eq, PartialEq)]
+#[derive(Debug, Clone, CosmicConfigEntry, Eq, PartialEq)]
 #[version = 1]
 pub struct Config {
-    demo: String,
+    /// Asks for a confirmation before the passphrase is revealed.
+    pub confirm_visibility: bool,
+    /// Locks the dialog until the user acknowledges a request from another host or user.
+    /// The host is the one of gpg-agent, requests forwarded over SSH to the local agent
+    /// look local.
+    pub confirm_foreign_requests: bool,
+    /// Displays an on-screen keypad when the PIN of a smartcard is requested.
+    pub pin_keypad: bool,
+    /// Shuffles the digits of the keypad every time it's displayed.
+    pub shuffle_keypad: bool,
+    /// Overrides `OPTION grab` and `no-grab` sent by the agent when set.
+    pub grab: Option<bool>,
+    /// Displays the dialog above everything, fullscreen windows included. By default, it's
+    /// on the same layer as the panels.
+    pub overlay: bool,
+    /// Forces a layer surface, or a regular window when false. By default, the layer surface
+    /// is used when the compositor supports it.
+    pub layer_shell: Option<bool>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            confirm_visibility: true,
+            confirm_foreign_requests: false,
+            pin_keypad: false,
+            shuffle_keypad: false,
+            grab: None,
+            overlay: false,
+            layer_shell: None,
+        }
+    }
+}
+
+impl Config {
+    /// Loads the configuration of zuul, any missing or invalid entry is replaced by its
+    /// default value.
+    pub fn load(app_id: &str) -> Self {
+        cosmic_config::Config::new(app_id, Self::VERSION)
+            .map(|context| match Self::get_entry(&context) {
+                Ok(config) => config,
+                Err((errors, config)) => {
+                    for err in errors {
+                        tracing::warn!("invalid configuration: {err}");
+                    }
+                    config
+                }
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn keypad(&self) -> Keypad {
+        match (self.pin_keypad, self.shuffle_keypad) {
+            (false, _) => Keypad::Hidden,
+            (true, false) => Keypad::Ordered,
+            (true, true) => Keypad::Shuffled,
+        }
+    }
 }
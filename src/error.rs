@@ -10,7 +10,7 @@ use assuan::ParseErr;
 pub enum ZuulErr {
     Input(ErrorKind),
     Parsing(ParseErr),
-    Output,
+    Output(ErrorKind),
 }
 
 impl std::error::Error for ZuulErr {}
@@ -19,7 +19,7 @@ impl std::fmt::Display for ZuulErr {
         match self {
             ZuulErr::Input(e) => write!(f, "error `{e}` while reading stdin input"),
             ZuulErr::Parsing(e) => write!(f, "error `{e}` while parsing pinentry commands"),
-            ZuulErr::Output => write!(f, "todo output"),
+            ZuulErr::Output(e) => write!(f, "error `{e}` while writing stdout output"),
         }
     }
 }
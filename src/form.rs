@@ -3,18 +3,56 @@
 // SPDX-License-Identifier: MIT
 
 use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use assuan::Command;
+use assuan::{Command, KeyInfo, OptionArgs, Owner};
+
+use crate::fl;
 
 #[derive(Default, Clone, Debug)]
 pub struct Form {
+    title: Option<String>,
     prompt: String,
     button_ok: String,
     button_cancel: String,
+    button_yes: String,
+    button_not_ok: Option<String>,
     description: Option<String>,
+    timeout: Option<Duration>,
+    touch_file: Option<PathBuf>,
+    tty_name: Option<PathBuf>,
+    repeat: Option<String>,
+    repeat_error: String,
+    error: Option<String>,
+    quality_bar: Option<String>,
+    quality_bar_tt: Option<String>,
+    constraints_enforce: bool,
+    constraints_hint_short: Option<String>,
+    constraints_hint_long: Option<String>,
+    gen_pin: Option<String>,
+    gen_pin_tt: Option<String>,
+    formatted_passphrase: bool,
+    formatted_passphrase_hint: Option<String>,
+    caps_hint: String,
+    yes: String,
+    no: String,
+    confirm_visibility: String,
+    tooltip_visibility: String,
+    tooltip_hide: String,
+    key_info: Option<KeyInfo>,
+    card: Option<String>,
+    pin: bool,
+    grab: bool,
+    password_manager: Option<String>,
+    owner: Option<Owner>,
 }
 
 impl Form {
+    pub fn title(&self) -> Option<Cow<str>> {
+        self.title.as_deref().map(Cow::Borrowed)
+    }
+
     pub fn prompt(&self) -> Cow<str> {
         Cow::Borrowed(&self.prompt)
     }
@@ -27,40 +65,326 @@ impl Form {
         Cow::Borrowed(&self.button_cancel)
     }
 
+    /// Label of the affirmative button of a CONFIRM dialog.
+    pub fn button_yes(&self) -> Cow<str> {
+        Cow::Borrowed(&self.button_yes)
+    }
+
+    pub fn button_not_ok(&self) -> Option<Cow<str>> {
+        self.button_not_ok.as_deref().map(Cow::Borrowed)
+    }
+
     pub fn description(&self) -> Option<Cow<str>> {
         self.description.as_deref().map(Cow::Borrowed)
     }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    pub fn touch_file(&self) -> Option<&Path> {
+        self.touch_file.as_deref()
+    }
+
+    /// Terminal of the user, used when there is no graphical session.
+    pub fn tty_name(&self) -> Option<&Path> {
+        self.tty_name.as_deref()
+    }
+
+    /// Label of the second input, only present when the passphrase needs to be repeated.
+    pub fn repeat(&self) -> Option<Cow<str>> {
+        self.repeat.as_deref().map(Cow::Borrowed)
+    }
+
+    pub fn repeat_error(&self) -> Cow<str> {
+        Cow::Borrowed(&self.repeat_error)
+    }
+
+    /// Label of the quality meter, only present when the agent can rate the passphrase.
+    pub fn quality_bar(&self) -> Option<Cow<str>> {
+        self.quality_bar.as_deref().map(Cow::Borrowed)
+    }
+
+    pub fn quality_bar_tt(&self) -> Option<Cow<str>> {
+        self.quality_bar_tt.as_deref().map(Cow::Borrowed)
+    }
+
+    /// True when the agent must validate the passphrase before it's accepted.
+    pub fn constraints_enforce(&self) -> bool {
+        self.constraints_enforce
+    }
+
+    pub fn constraints_hint_short(&self) -> Option<Cow<str>> {
+        self.constraints_hint_short.as_deref().map(Cow::Borrowed)
+    }
+
+    pub fn constraints_hint_long(&self) -> Option<Cow<str>> {
+        self.constraints_hint_long.as_deref().map(Cow::Borrowed)
+    }
+
+    /// Label of the button generating a passphrase, only present when the agent offers one.
+    pub fn gen_pin(&self) -> Option<Cow<str>> {
+        self.gen_pin.as_deref().map(Cow::Borrowed)
+    }
+
+    pub fn gen_pin_tt(&self) -> Option<Cow<str>> {
+        self.gen_pin_tt.as_deref().map(Cow::Borrowed)
+    }
+
+    /// True when the passphrase is displayed in groups of characters, the agent asks for it
+    /// with generated passphrases.
+    pub fn formatted_passphrase(&self) -> bool {
+        self.formatted_passphrase
+    }
+
+    pub fn formatted_passphrase_hint(&self) -> Option<Cow<str>> {
+        self.formatted_passphrase_hint.as_deref().map(Cow::Borrowed)
+    }
+
+    /// Warning displayed while Caps Lock is active.
+    pub fn caps_hint(&self) -> Cow<str> {
+        Cow::Borrowed(&self.caps_hint)
+    }
+
+    /// Labels answering the questions asked by zuul itself.
+    pub fn yes(&self) -> Cow<str> {
+        Cow::Borrowed(&self.yes)
+    }
+
+    pub fn no(&self) -> Cow<str> {
+        Cow::Borrowed(&self.no)
+    }
+
+    /// Question asked before the passphrase is revealed.
+    pub fn confirm_visibility(&self) -> Cow<str> {
+        Cow::Borrowed(&self.confirm_visibility)
+    }
+
+    pub fn tooltip_visibility(&self) -> Cow<str> {
+        Cow::Borrowed(&self.tooltip_visibility)
+    }
+
+    pub fn tooltip_hide(&self) -> Cow<str> {
+        Cow::Borrowed(&self.tooltip_hide)
+    }
+
+    /// False when the agent lets other windows receive the keyboard while the dialog is
+    /// displayed, see `OPTION no-grab`.
+    pub fn grab(&self) -> bool {
+        self.grab
+    }
+
+    /// Process that asked the agent for the passphrase.
+    pub fn owner(&self) -> Option<&Owner> {
+        self.owner.as_ref()
+    }
+
+    pub fn key_info(&self) -> Option<&KeyInfo> {
+        self.key_info.as_ref()
+    }
+
+    /// Serial number of the smartcard whose PIN is requested.
+    pub fn card(&self) -> Option<&str> {
+        self.card.as_deref()
+    }
+
+    /// True when the PIN of a smartcard is requested, it only contains digits.
+    pub fn pin(&self) -> bool {
+        self.pin
+    }
+
+    /// Label of the checkbox remembering the passphrase, only present when the agent allows
+    /// zuul to cache the passphrase of the key.
+    pub fn password_manager(&self) -> Option<Cow<str>> {
+        self.password_manager.as_deref().map(Cow::Borrowed)
+    }
+
+    /// Why the previous attempt failed, usually a bad passphrase.
+    pub fn error(&self) -> Option<Cow<str>> {
+        self.error.as_deref().map(Cow::Borrowed)
+    }
+}
+
+/// Labels sent by the agent with `OPTION default-*`, they are used when the dialog doesn't
+/// set its own label and take precedence over the labels of zuul.
+#[derive(Default)]
+struct Defaults {
+    prompt: Option<String>,
+    ok: Option<String>,
+    cancel: Option<String>,
+    yes: Option<String>,
+    no: Option<String>,
+    caps_hint: Option<String>,
+    cf_visi: Option<String>,
+    tt_visi: Option<String>,
+    tt_hide: Option<String>,
+    pwmngr: Option<String>,
 }
 
 struct FormBuilder {
-    prompt: String,
-    button_ok: String,
-    button_cancel: String,
+    defaults: Defaults,
+    title: Option<String>,
+    prompt: Option<String>,
+    button_ok: Option<String>,
+    button_cancel: Option<String>,
+    button_not_ok: Option<String>,
     description: Option<String>,
+    timeout: Option<Duration>,
+    touch_file: Option<PathBuf>,
+    tty_name: Option<PathBuf>,
+    repeat: Option<String>,
+    repeat_error: Option<String>,
+    error: Option<String>,
+    quality_bar: Option<String>,
+    quality_bar_tt: Option<String>,
+    constraints_enforce: bool,
+    constraints_hint_short: Option<String>,
+    constraints_hint_long: Option<String>,
+    gen_pin: Option<String>,
+    gen_pin_tt: Option<String>,
+    formatted_passphrase: bool,
+    formatted_passphrase_hint: Option<String>,
+    key_info: Option<KeyInfo>,
+    card: Option<String>,
+    allow_external_password_cache: bool,
+    owner: Option<Owner>,
+    grab: bool,
 }
 
 impl FormBuilder {
     fn new() -> Self {
         Self {
-            prompt: String::from("PIN:"),
-            button_ok: String::from("OK"),
-            button_cancel: String::from("cancel"),
+            defaults: Defaults::default(),
+            title: None,
+            prompt: None,
+            button_ok: None,
+            button_cancel: None,
+            button_not_ok: None,
             description: None,
+            timeout: None,
+            touch_file: None,
+            tty_name: None,
+            repeat: None,
+            repeat_error: None,
+            error: None,
+            quality_bar: None,
+            quality_bar_tt: None,
+            constraints_enforce: false,
+            constraints_hint_short: None,
+            constraints_hint_long: None,
+            gen_pin: None,
+            gen_pin_tt: None,
+            formatted_passphrase: false,
+            formatted_passphrase_hint: None,
+            key_info: None,
+            card: None,
+            allow_external_password_cache: false,
+            owner: None,
+            grab: true,
         }
     }
 
+    fn with_title(mut self, s: impl Into<String>) -> Self {
+        self.title = Some(s.into());
+        self
+    }
+
     fn with_prompt(mut self, s: impl Into<String>) -> Self {
-        self.prompt = s.into();
+        self.prompt = Some(s.into());
         self
     }
 
     fn with_button_ok(mut self, s: impl Into<String>) -> Self {
-        self.button_ok = s.into();
+        self.button_ok = Some(s.into());
         self
     }
 
     fn with_button_cancel(mut self, s: impl Into<String>) -> Self {
-        self.button_cancel = s.into();
+        self.button_cancel = Some(s.into());
+        self
+    }
+
+    fn with_button_not_ok(mut self, s: impl Into<String>) -> Self {
+        self.button_not_ok = Some(s.into());
+        self
+    }
+
+    fn with_default_prompt(mut self, s: impl Into<String>) -> Self {
+        self.defaults.prompt = Some(s.into());
+        self
+    }
+
+    fn with_default_ok(mut self, s: impl Into<String>) -> Self {
+        self.defaults.ok = Some(s.into());
+        self
+    }
+
+    fn with_default_cancel(mut self, s: impl Into<String>) -> Self {
+        self.defaults.cancel = Some(s.into());
+        self
+    }
+
+    fn with_default_yes(mut self, s: impl Into<String>) -> Self {
+        self.defaults.yes = Some(s.into());
+        self
+    }
+
+    fn with_default_no(mut self, s: impl Into<String>) -> Self {
+        self.defaults.no = Some(s.into());
+        self
+    }
+
+    fn with_default_caps_hint(mut self, s: impl Into<String>) -> Self {
+        self.defaults.caps_hint = Some(s.into());
+        self
+    }
+
+    fn with_default_cf_visi(mut self, s: impl Into<String>) -> Self {
+        self.defaults.cf_visi = Some(s.into());
+        self
+    }
+
+    fn with_default_tt_visi(mut self, s: impl Into<String>) -> Self {
+        self.defaults.tt_visi = Some(s.into());
+        self
+    }
+
+    fn with_default_tt_hide(mut self, s: impl Into<String>) -> Self {
+        self.defaults.tt_hide = Some(s.into());
+        self
+    }
+
+    fn with_default_pwmngr(mut self, s: impl Into<String>) -> Self {
+        self.defaults.pwmngr = Some(s.into());
+        self
+    }
+
+    // SETKEYINFO --clear removes the key.
+    fn with_key_info(mut self, k: Option<KeyInfo>) -> Self {
+        self.key_info = k;
+        self.card = None;
+        self
+    }
+
+    fn with_card_key_info(mut self, serial: impl Into<String>) -> Self {
+        self.key_info = None;
+        self.card = Some(serial.into());
+        self
+    }
+
+    fn with_owner(mut self, o: Option<Owner>) -> Self {
+        self.owner = o;
+        self
+    }
+
+    // `OPTION grab` and `no-grab`, the keyboard is grabbed when neither is sent.
+    fn with_grab(mut self, grab: bool) -> Self {
+        self.grab = grab;
+        self
+    }
+
+    fn with_allow_external_password_cache(mut self) -> Self {
+        self.allow_external_password_cache = true;
         self
     }
 
@@ -69,26 +393,235 @@ impl FormBuilder {
         self
     }
 
+    // A timeout of zero means that the dialog stays open until the user answers.
+    fn with_timeout(mut self, d: Duration) -> Self {
+        self.timeout = (!d.is_zero()).then_some(d);
+        self
+    }
+
+    fn with_touch_file(mut self, s: impl Into<PathBuf>) -> Self {
+        self.touch_file = Some(s.into());
+        self
+    }
+
+    fn with_tty_name(mut self, s: impl Into<PathBuf>) -> Self {
+        self.tty_name = Some(s.into());
+        self
+    }
+
+    // The agent may send SETREPEAT without a label, the default label is used in that case.
+    fn with_repeat(mut self, s: impl Into<String>) -> Self {
+        self.repeat = Some(s.into());
+        self
+    }
+
+    fn with_repeat_error(mut self, s: impl Into<String>) -> Self {
+        self.repeat_error = Some(s.into());
+        self
+    }
+
+    fn with_error(mut self, s: impl Into<String>) -> Self {
+        self.error = Some(s.into());
+        self
+    }
+
+    // The agent may send SETQUALITYBAR without a label.
+    fn with_quality_bar(mut self, s: impl Into<String>) -> Self {
+        self.quality_bar = Some(s.into());
+        self
+    }
+
+    fn with_quality_bar_tt(mut self, s: impl Into<String>) -> Self {
+        self.quality_bar_tt = Some(s.into());
+        self
+    }
+
+    fn with_constraints_enforce(mut self) -> Self {
+        self.constraints_enforce = true;
+        self
+    }
+
+    fn with_constraints_hint_short(mut self, s: impl Into<String>) -> Self {
+        self.constraints_hint_short = Some(s.into());
+        self
+    }
+
+    fn with_constraints_hint_long(mut self, s: impl Into<String>) -> Self {
+        self.constraints_hint_long = Some(s.into());
+        self
+    }
+
+    // The agent may send SETGENPIN without a label.
+    fn with_gen_pin(mut self, s: impl Into<String>) -> Self {
+        self.gen_pin = Some(s.into());
+        self
+    }
+
+    fn with_gen_pin_tt(mut self, s: impl Into<String>) -> Self {
+        self.gen_pin_tt = Some(s.into());
+        self
+    }
+
+    fn with_formatted_passphrase(mut self) -> Self {
+        self.formatted_passphrase = true;
+        self
+    }
+
+    fn with_formatted_passphrase_hint(mut self, s: impl Into<String>) -> Self {
+        self.formatted_passphrase_hint = Some(s.into());
+        self
+    }
+
+    // Labels are resolved in order from the SET* commands, the OPTION default-* and finally
+    // the localized labels of zuul.
     fn build(self) -> Form {
+        let defaults = self.defaults;
+        let pin = self.card.is_some()
+            || self.prompt.as_deref().is_some_and(is_pin_prompt)
+            || self.description.as_deref().is_some_and(is_pin_description);
+        let yes = defaults.yes.unwrap_or_else(|| fl!("button-yes"));
+        let no = defaults.no.unwrap_or_else(|| fl!("button-no"));
+
         Form {
-            prompt: self.prompt,
-            button_ok: self.button_ok,
-            button_cancel: self.button_cancel,
+            title: self.title,
+            prompt: self
+                .prompt
+                .or(defaults.prompt)
+                .unwrap_or_else(|| fl!("label-prompt")),
+            button_yes: self.button_ok.clone().unwrap_or_else(|| yes.clone()),
+            button_ok: self
+                .button_ok
+                .or(defaults.ok)
+                .unwrap_or_else(|| fl!("button-ok")),
+            button_cancel: self
+                .button_cancel
+                .or(defaults.cancel)
+                .unwrap_or_else(|| fl!("button-cancel")),
+            button_not_ok: self
+                .button_not_ok
+                .map(|s| or_default(s, Some(no.clone()), String::new)),
             description: self.description,
+            timeout: self.timeout,
+            touch_file: self.touch_file,
+            tty_name: self.tty_name,
+            repeat: self
+                .repeat
+                .map(|s| or_default(s, None, || fl!("label-repeat"))),
+            repeat_error: self
+                .repeat_error
+                .unwrap_or_else(|| fl!("label-repeat-error")),
+            error: self.error,
+            quality_bar: self
+                .quality_bar
+                .map(|s| or_default(s, None, || fl!("label-quality-bar"))),
+            quality_bar_tt: self.quality_bar_tt,
+            constraints_enforce: self.constraints_enforce,
+            constraints_hint_short: self.constraints_hint_short,
+            constraints_hint_long: self.constraints_hint_long,
+            gen_pin: self
+                .gen_pin
+                .map(|s| or_default(s, None, || fl!("button-gen-pin"))),
+            gen_pin_tt: self.gen_pin_tt,
+            formatted_passphrase: self.formatted_passphrase,
+            formatted_passphrase_hint: self.formatted_passphrase_hint,
+            caps_hint: defaults.caps_hint.unwrap_or_else(|| fl!("label-caps-hint")),
+            yes,
+            no,
+            confirm_visibility: defaults
+                .cf_visi
+                .unwrap_or_else(|| fl!("label-confirm-visibility")),
+            tooltip_visibility: defaults
+                .tt_visi
+                .unwrap_or_else(|| fl!("tooltip-visibility")),
+            tooltip_hide: defaults.tt_hide.unwrap_or_else(|| fl!("tooltip-hide")),
+            password_manager: (self.allow_external_password_cache && self.key_info.is_some()).then(
+                || {
+                    defaults
+                        .pwmngr
+                        .unwrap_or_else(|| fl!("label-password-manager"))
+                },
+            ),
+            key_info: self.key_info,
+            card: self.card,
+            pin,
+            grab: self.grab,
+            owner: self.owner,
         }
     }
 }
 
+// Commands like SETREPEAT can be sent without a label to display the default one.
+fn or_default(s: String, default: Option<String>, fallback: impl FnOnce() -> String) -> String {
+    if s.is_empty() {
+        default.unwrap_or_else(fallback)
+    } else {
+        s
+    }
+}
+
+// The agent asks for the PIN of a smartcard with prompts like `PIN`, `Admin PIN` or `New PIN`.
+fn is_pin_prompt(prompt: &str) -> bool {
+    let prompt = prompt.trim_end().trim_end_matches(':');
+    prompt == "PIN" || prompt.ends_with(" PIN")
+}
+
+// Descriptions written by scdaemon, like `Please enter the PIN%0A[sigs done: 3]`.
+fn is_pin_description(description: &str) -> bool {
+    description.lines().next().is_some_and(|line| {
+        line.strip_prefix("Please enter the ")
+            .is_some_and(|rest| rest.starts_with("PIN") || rest.contains(" PIN"))
+    })
+}
+
 pub fn apply_commands(commands: &[Command]) -> Form {
     let mut b = FormBuilder::new();
 
     for command in commands {
         // iteratively building the form.
         b = match command {
+            Command::SetTitle(t) => b.with_title(t),
             Command::SetPrompt(p) => b.with_prompt(p),
             Command::SetOk(t) => b.with_button_ok(t),
             Command::SetCancel(t) => b.with_button_cancel(t),
+            Command::SetNotOk(t) => b.with_button_not_ok(t),
             Command::SetDesc(t) => b.with_description(t),
+            Command::SetTimeOut(d) => b.with_timeout(*d),
+            Command::Option(OptionArgs::TouchFile(f)) => b.with_touch_file(f),
+            Command::Option(OptionArgs::TtyName(t)) => b.with_tty_name(t),
+            Command::Option(OptionArgs::DefaultPrompt(t)) => b.with_default_prompt(t),
+            Command::Option(OptionArgs::DefaultOk(t)) => b.with_default_ok(t),
+            Command::Option(OptionArgs::DefaultCancel(t)) => b.with_default_cancel(t),
+            Command::Option(OptionArgs::DefaultYes(t)) => b.with_default_yes(t),
+            Command::Option(OptionArgs::DefaultNo(t)) => b.with_default_no(t),
+            Command::Option(OptionArgs::DefaultCapsHint(t)) => b.with_default_caps_hint(t),
+            Command::Option(OptionArgs::DefaultCFVisi(t)) => b.with_default_cf_visi(t),
+            Command::Option(OptionArgs::DefaultTTVisi(t)) => b.with_default_tt_visi(t),
+            Command::Option(OptionArgs::DefaultTTHide(t)) => b.with_default_tt_hide(t),
+            Command::Option(OptionArgs::DefaultPwmngr(t)) => b.with_default_pwmngr(t),
+            Command::Option(OptionArgs::AllowExternalPasswordCache) => {
+                b.with_allow_external_password_cache()
+            }
+            Command::SetKeyInfo(k) => b.with_key_info(k.clone()),
+            Command::SetCardKeyInfo(serial) => b.with_card_key_info(serial),
+            Command::Option(OptionArgs::Owner(o)) => b.with_owner(o.clone()),
+            Command::Option(OptionArgs::Grab) => b.with_grab(true),
+            Command::Option(OptionArgs::NoGrab) => b.with_grab(false),
+            Command::SetRepeat(t) => b.with_repeat(t),
+            Command::SetRepeatError(t) => b.with_repeat_error(t),
+            Command::SetError(t) => b.with_error(t),
+            Command::SetQualityBar(t) => b.with_quality_bar(t),
+            Command::SetQualityBarTT(t) => b.with_quality_bar_tt(t),
+            Command::Option(OptionArgs::ConstraintsEnforce) => b.with_constraints_enforce(),
+            Command::Option(OptionArgs::ConstraintsHintShort(t)) => {
+                b.with_constraints_hint_short(t)
+            }
+            Command::Option(OptionArgs::ConstraintsHintLong(t)) => b.with_constraints_hint_long(t),
+            Command::SetGenPin(t) => b.with_gen_pin(t),
+            Command::SetGenPinTT(t) => b.with_gen_pin_tt(t),
+            Command::Option(OptionArgs::FormattedPassphrase) => b.with_formatted_passphrase(),
+            Command::Option(OptionArgs::FormattedPassphraseHint(t)) => {
+                b.with_formatted_passphrase_hint(t)
+            }
             _ => continue, // ignore unsupported commands for now.
         };
     }
@@ -0,0 +1,29 @@
+// SPDX-FileCopyrightText: 2025 Pier-Hugues Pellerin <ph@heykimo.com>
+//
+// SPDX-License-Identifier: MIT
+
+use std::path::{Path, PathBuf};
+
+/// Size in pixels of the icons displayed in the dialog header.
+pub const HEADER_ICON_SIZE: u16 = 32;
+
+/// Size in pixels of the icon of the application requesting the passphrase.
+pub const REQUESTER_ICON_SIZE: u16 = 16;
+
+/// Finds an icon in the current icon theme, falling back on the hicolor theme.
+pub fn lookup(name: &str, size: u16) -> Option<PathBuf> {
+    freedesktop_icons::lookup(name)
+        .with_theme(&cosmic::icon_theme::default())
+        .with_size(size)
+        .with_cache()
+        .find()
+}
+
+/// Finds the icon of a desktop entry, which can either be a name or an absolute path.
+pub fn lookup_entry_icon(icon: &str, size: u16) -> Option<PathBuf> {
+    let path = Path::new(icon);
+    if path.is_absolute() {
+        return path.exists().then(|| path.to_path_buf());
+    }
+    lookup(icon, size)
+}
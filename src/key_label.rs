@@ -0,0 +1,204 @@
+// SPDX-FileCopyrightText: 2025 Pier-Hugues Pellerin <ph@heykimo.com>
+//
+// SPDX-License-Identifier: MIT
+
+use std::path::PathBuf;
+
+/// Human readable description of a key, read from its file in `private-keys-v1.d`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyLabel {
+    /// Set by the user with `gpg-connect-agent 'KEYATTR <grip> Label: ...'`.
+    Label(String),
+    /// Serial number of the smartcard holding the key.
+    Token(String),
+    /// Comment of a key added with ssh-add.
+    Comment(String),
+}
+
+/// Reads the label of the key from the home directory of GnuPG, honouring `GNUPGHOME`.
+pub fn lookup(keygrip: &str) -> Option<KeyLabel> {
+    // The keygrip comes from the agent, but it's still used to build a path.
+    if !keygrip.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let path = gnupg_home()?
+        .join("private-keys-v1.d")
+        .join(format!("{keygrip}.key"));
+    let mut contents = std::fs::read(path).ok()?;
+    // Parsing borrows the contents, nothing but the label is copied.
+    let label = std::str::from_utf8(&contents).ok().and_then(parse);
+
+    // The file holds the protected private key, don't leave it around.
+    contents.fill(0);
+    std::hint::black_box(&contents);
+
+    label
+}
+
+fn gnupg_home() -> Option<PathBuf> {
+    std::env::var_os("GNUPGHOME")
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".gnupg")))
+}
+
+// Keys use the extended format of GnuPG, a list of `Name: value` entries where a value
+// continues on the following lines starting with a space.
+fn parse(contents: &str) -> Option<KeyLabel> {
+    let mut label = None;
+    let mut token = None;
+    let mut comment = None;
+
+    for (name, value) in entries(contents) {
+        match name.to_ascii_lowercase().as_str() {
+            "label" => label = label.or_else(|| Some(unfold(value))),
+            // `<serialno> <idstr> ...`
+            "token" => token = token.or_else(|| value.split_whitespace().next().map(str::to_owned)),
+            "comment" => comment = comment.or_else(|| Some(unfold(value))),
+            // Older keys keep the comment of SSH keys in the S-expression.
+            "key" => comment = comment.or_else(|| sexp_comment(value)),
+            _ => {}
+        }
+    }
+
+    label
+        .filter(|l| !l.is_empty())
+        .map(KeyLabel::Label)
+        .or(token.map(KeyLabel::Token))
+        .or(comment.filter(|c| !c.is_empty()).map(KeyLabel::Comment))
+}
+
+// The names with the raw text of their value, continuation lines included.
+fn entries(contents: &str) -> Vec<(&str, &str)> {
+    let mut entries: Vec<(&str, usize, usize)> = Vec::new();
+    let mut start = 0;
+
+    for line in contents.split_inclusive('\n') {
+        let end = start + line.len();
+
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, _, value_end)) = entries.last_mut() {
+                *value_end = end;
+            }
+        } else if let Some((name, _)) = line.split_once(':') {
+            entries.push((name.trim(), start + name.len() + 1, end));
+        }
+
+        start = end;
+    }
+
+    entries
+        .into_iter()
+        .map(|(name, value_start, value_end)| (name, &contents[value_start..value_end]))
+        .collect()
+}
+
+// Joins the lines of a value with single spaces.
+fn unfold(value: &str) -> String {
+    value
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn sexp_comment(key: &str) -> Option<String> {
+    let start = key.find("(comment ")? + "(comment ".len();
+    let rest = &key[start..];
+
+    let comment = match rest.strip_prefix('"') {
+        Some(quoted) => &quoted[..quoted.find('"')?],
+        None => &rest[..rest.find(')')?],
+    };
+    Some(comment.to_owned())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_label() {
+        let contents = "Created: 20240102T030405\nLabel: Work laptop\nKey: (private-key)\n";
+        assert_eq!(
+            Some(KeyLabel::Label("Work laptop".to_string())),
+            parse(contents)
+        );
+    }
+
+    #[test]
+    fn parse_multiline_label() {
+        let contents = "Label: Signing key\n  for releases\n";
+        assert_eq!(
+            Some(KeyLabel::Label("Signing key for releases".to_string())),
+            parse(contents)
+        );
+    }
+
+    #[test]
+    fn parse_token() {
+        let contents = "Token: D2760001240103040006123456780000 OPENPGP.1 - -\n";
+        assert_eq!(
+            Some(KeyLabel::Token(
+                "D2760001240103040006123456780000".to_string()
+            )),
+            parse(contents)
+        );
+    }
+
+    #[test]
+    fn label_has_precedence() {
+        let contents = "Token: D276000124 OPENPGP.1\nLabel: Yubikey\n";
+        assert_eq!(
+            Some(KeyLabel::Label("Yubikey".to_string())),
+            parse(contents)
+        );
+    }
+
+    #[test]
+    fn parse_ssh_comment() {
+        let contents =
+            "Key: (private-key (ecc (curve Ed25519)(q #00#)(d #00#))\n (comment \"ph@laptop\"))\n";
+        assert_eq!(
+            Some(KeyLabel::Comment("ph@laptop".to_string())),
+            parse(contents)
+        );
+    }
+
+    #[test]
+    fn parse_unquoted_ssh_comment() {
+        let contents = "Key: (private-key (ecc (curve Ed25519))(comment ph@laptop))\n";
+        assert_eq!(
+            Some(KeyLabel::Comment("ph@laptop".to_string())),
+            parse(contents)
+        );
+    }
+
+    #[test]
+    fn entries_borrow_the_contents() {
+        let contents = "Label: Work\nKey: (private-key\n (comment x))\nCreated: 2024\n";
+        assert_eq!(
+            vec![
+                ("Label", " Work\n"),
+                ("Key", " (private-key\n (comment x))\n"),
+                ("Created", " 2024\n"),
+            ],
+            entries(contents)
+        );
+    }
+
+    #[test]
+    fn parse_without_label() {
+        assert_eq!(
+            None,
+            parse("Created: 20240102T030405\nKey: (private-key)\n")
+        );
+    }
+
+    #[test]
+    fn lookup_rejects_paths() {
+        assert_eq!(None, lookup("../../etc/passwd"));
+    }
+}
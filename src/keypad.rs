@@ -0,0 +1,102 @@
+// SPDX-FileCopyrightText: 2025 Pier-Hugues Pellerin <ph@heykimo.com>
+//
+// SPDX-License-Identifier: MIT
+
+use std::io::Error;
+use tracing::warn;
+
+/// Digits of the keypad, from the top left key like a phone.
+const DIGITS: [char; 10] = ['1', '2', '3', '4', '5', '6', '7', '8', '9', '0'];
+
+/// On-screen numeric keypad offered when the PIN of a smartcard is requested.
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+pub enum Keypad {
+    #[default]
+    Hidden,
+    Ordered,
+    /// The digits are shuffled every time a dialog is opened, so the position of the keys
+    /// pressed doesn't reveal the PIN to someone looking over the shoulder.
+    Shuffled,
+}
+
+impl Keypad {
+    /// The digits of the keys from the top left, `None` when the keypad is hidden.
+    pub fn layout(self) -> Option<[char; 10]> {
+        match self {
+            Keypad::Hidden => None,
+            Keypad::Ordered => Some(DIGITS),
+            Keypad::Shuffled => Some(shuffle(DIGITS).unwrap_or_else(|err| {
+                warn!("can't shuffle the keypad: {err}");
+                DIGITS
+            })),
+        }
+    }
+}
+
+// Fisher-Yates shuffle.
+fn shuffle(mut digits: [char; 10]) -> std::io::Result<[char; 10]> {
+    for i in (1..digits.len()).rev() {
+        digits.swap(i, random_below(i as u32 + 1)? as usize);
+    }
+    Ok(digits)
+}
+
+// Uniform value in `0..bound`, the values that would bias the result are drawn again.
+fn random_below(bound: u32) -> std::io::Result<u32> {
+    let zone = u32::MAX - u32::MAX % bound;
+    loop {
+        let value = random_u32()?;
+        if value < zone {
+            return Ok(value % bound);
+        }
+    }
+}
+
+fn random_u32() -> std::io::Result<u32> {
+    let mut buf = [0u8; 4];
+    loop {
+        // SAFETY: `buf` is valid for its whole length.
+        let read = unsafe { libc::getrandom(buf.as_mut_ptr().cast(), buf.len(), 0) };
+        if read == buf.len() as isize {
+            return Ok(u32::from_ne_bytes(buf));
+        }
+
+        // Small reads are only interrupted before anything is written.
+        let err = Error::last_os_error();
+        if err.raw_os_error() != Some(libc::EINTR) {
+            return Err(err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn hidden_keypad_has_no_layout() {
+        assert_eq!(None, Keypad::Hidden.layout());
+    }
+
+    #[test]
+    fn ordered_layout() {
+        assert_eq!(
+            Some(['1', '2', '3', '4', '5', '6', '7', '8', '9', '0']),
+            Keypad::Ordered.layout()
+        );
+    }
+
+    #[test]
+    fn shuffled_layout_has_every_digit() {
+        let mut layout = Keypad::Shuffled.layout().unwrap();
+        layout.sort();
+        assert_eq!(['0', '1', '2', '3', '4', '5', '6', '7', '8', '9'], layout);
+    }
+
+    #[test]
+    fn random_below_stays_in_bounds() {
+        for bound in 1..=10 {
+            assert!(random_below(bound).unwrap() < bound);
+        }
+    }
+}
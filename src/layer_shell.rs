@@ -0,0 +1,43 @@
+// SPDX-FileCopyrightText: 2025 Pier-Hugues Pellerin <ph@heykimo.com>
+//
+// SPDX-License-Identifier: MIT
+
+use tracing::warn;
+use wayland_client::globals::{GlobalListContents, registry_queue_init};
+use wayland_client::protocol::wl_registry::{self, WlRegistry};
+use wayland_client::{Connection, Dispatch, QueueHandle};
+
+const LAYER_SHELL: &str = "zwlr_layer_shell_v1";
+
+/// True when the compositor can display layer surfaces, it's never the case under X11 and
+/// some Wayland compositors don't implement wlr-layer-shell.
+pub fn is_available() -> bool {
+    let Ok(connection) = Connection::connect_to_env() else {
+        return false;
+    };
+
+    match registry_queue_init::<Registry>(&connection) {
+        Ok((globals, _)) => globals
+            .contents()
+            .with_list(|globals| globals.iter().any(|global| global.interface == LAYER_SHELL)),
+        Err(err) => {
+            warn!("can't list the globals of the compositor: {err}");
+            false
+        }
+    }
+}
+
+struct Registry;
+
+// The globals are only listed once at startup, later changes are ignored.
+impl Dispatch<WlRegistry, GlobalListContents> for Registry {
+    fn event(
+        _: &mut Self,
+        _: &WlRegistry,
+        _: wl_registry::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
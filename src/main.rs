@@ -4,12 +4,24 @@
 
 use tracing::info;
 
+mod agent;
 mod app;
+mod caps_lock;
 mod config;
 mod error;
 mod form;
 mod i18n;
+mod icons;
+mod key_label;
+mod keypad;
+mod layer_shell;
+mod mnemonic;
+mod requester;
+mod secret_store;
+mod session;
 mod subscription;
+mod touch;
+mod tty;
 
 fn main() -> cosmic::iced::Result {
     init_logging();
@@ -22,6 +34,15 @@ fn main() -> cosmic::iced::Result {
     // Enable localizations to be applied.
     i18n::init(&requested_languages);
 
+    // Nothing can be drawn without a graphical session, like over SSH or on a console.
+    if !has_display() {
+        let config = config::Config::load(<app::Zuul as cosmic::Application>::APP_ID);
+        let session = session::Session::new(session::SystemClock, secret_store::KernelKeyring)
+            .with_foreign_confirmation(config.confirm_foreign_requests);
+        let ttyname = tty::ttyname_arg(std::env::args().skip(1));
+        std::process::exit(tty::run(session, ttyname));
+    }
+
     // Settings for configuring the application window and iced runtime.
     let settings = cosmic::app::Settings::default()
         .antialiasing(true)
@@ -34,6 +55,12 @@ fn main() -> cosmic::iced::Result {
     cosmic::app::run::<app::Zuul>(settings, ())
 }
 
+fn has_display() -> bool {
+    ["WAYLAND_DISPLAY", "DISPLAY"]
+        .iter()
+        .any(|var| std::env::var_os(var).is_some_and(|v| !v.is_empty()))
+}
+
 fn init_logging() {
     use tracing_subscriber::{EnvFilter, fmt, layer::SubscriberExt, util::SubscriberInitExt};
 
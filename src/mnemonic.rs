@@ -0,0 +1,135 @@
+// SPDX-FileCopyrightText: 2025 Pier-Hugues Pellerin <ph@heykimo.com>
+//
+// SPDX-License-Identifier: MIT
+
+/// Label using GTK-style mnemonics as sent by gpg-agent, `_Save` is displayed as `Save` and
+/// Alt+S triggers it. A double underscore is displayed as a single one.
+#[derive(Debug, PartialEq)]
+pub struct Mnemonic {
+    text: String,
+    // Byte offset of the accelerator in `text`.
+    accelerator: Option<usize>,
+}
+
+impl Mnemonic {
+    pub fn parse(label: &str) -> Self {
+        let mut text = String::with_capacity(label.len());
+        let mut accelerator = None;
+        let mut chars = label.chars();
+
+        while let Some(c) = chars.next() {
+            if c != '_' {
+                text.push(c);
+                continue;
+            }
+
+            match chars.next() {
+                Some('_') => text.push('_'),
+                // Only the first mnemonic is used, like GTK.
+                Some(c) => {
+                    accelerator.get_or_insert(text.len());
+                    text.push(c);
+                }
+                None => text.push('_'),
+            }
+        }
+
+        Self { text, accelerator }
+    }
+
+    /// The label without the mnemonic markers.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn key(&self) -> Option<char> {
+        self.text[self.accelerator?..].chars().next()
+    }
+
+    /// The label split around the accelerator, so it can be underlined.
+    pub fn split(&self) -> (&str, Option<&str>, &str) {
+        match (self.accelerator, self.key()) {
+            (Some(start), Some(key)) => {
+                let end = start + key.len_utf8();
+                (
+                    &self.text[..start],
+                    Some(&self.text[start..end]),
+                    &self.text[end..],
+                )
+            }
+            _ => (&self.text, None, ""),
+        }
+    }
+
+    /// True when the key pressed with Alt triggers this label, the case is ignored.
+    pub fn is_triggered_by(&self, key: &str) -> bool {
+        self.key().is_some_and(|k| {
+            k.to_lowercase()
+                .eq(key.chars().flat_map(char::to_lowercase))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_without_mnemonic() {
+        let mnemonic = Mnemonic::parse("OK");
+        assert_eq!("OK", mnemonic.text());
+        assert_eq!(None, mnemonic.key());
+        assert_eq!(("OK", None, ""), mnemonic.split());
+    }
+
+    #[test]
+    fn parse_mnemonic() {
+        let mnemonic = Mnemonic::parse("_Save in password manager");
+        assert_eq!("Save in password manager", mnemonic.text());
+        assert_eq!(Some('S'), mnemonic.key());
+        assert_eq!(("", Some("S"), "ave in password manager"), mnemonic.split());
+    }
+
+    #[test]
+    fn parse_mnemonic_in_the_middle() {
+        let mnemonic = Mnemonic::parse("Can_cel");
+        assert_eq!("Cancel", mnemonic.text());
+        assert_eq!(("Can", Some("c"), "el"), mnemonic.split());
+    }
+
+    #[test]
+    fn parse_escaped_underscore() {
+        let mnemonic = Mnemonic::parse("my__file_name");
+        assert_eq!("my_filename", mnemonic.text());
+        assert_eq!(Some('n'), mnemonic.key());
+    }
+
+    #[test]
+    fn parse_keeps_only_the_first_mnemonic() {
+        let mnemonic = Mnemonic::parse("_Yes _No");
+        assert_eq!("Yes No", mnemonic.text());
+        assert_eq!(Some('Y'), mnemonic.key());
+    }
+
+    #[test]
+    fn parse_trailing_underscore() {
+        let mnemonic = Mnemonic::parse("OK_");
+        assert_eq!("OK_", mnemonic.text());
+        assert_eq!(None, mnemonic.key());
+    }
+
+    #[test]
+    fn parse_non_ascii_accelerator() {
+        let mnemonic = Mnemonic::parse("_Échap");
+        assert_eq!(("", Some("É"), "chap"), mnemonic.split());
+        assert!(mnemonic.is_triggered_by("é"));
+    }
+
+    #[test]
+    fn triggered_ignores_the_case() {
+        let mnemonic = Mnemonic::parse("_OK");
+        assert!(mnemonic.is_triggered_by("o"));
+        assert!(mnemonic.is_triggered_by("O"));
+        assert!(!mnemonic.is_triggered_by("k"));
+    }
+}
@@ -0,0 +1,310 @@
+// SPDX-FileCopyrightText: 2025 Pier-Hugues Pellerin <ph@heykimo.com>
+//
+// SPDX-License-Identifier: MIT
+
+use assuan::Owner;
+use std::ffi::OsStr;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+/// Application that asked the agent for the passphrase, see `OPTION owner`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Requester {
+    pub name: String,
+    /// Name or path of the icon, as found in the desktop entry.
+    pub icon: Option<String>,
+    pub executable: PathBuf,
+    pub command_line: Vec<String>,
+}
+
+/// Why a request doesn't come from the user running zuul, see `foreign` for what can be told.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Foreign {
+    Host(String),
+    User(u32),
+}
+
+/// Compares the owner to the local host and user.
+///
+/// The hostname of `OPTION owner` is the one of the host running gpg-agent, not of the
+/// client. This flags an agent on another host drawing its dialogs on this display, or an
+/// agent of another user. A client using a socket of the local agent forwarded over SSH
+/// can't be told apart from a local one: the agent sees the local SSH process. An owner
+/// without a uid is only compared by host.
+pub fn foreign(owner: &Owner) -> Option<Foreign> {
+    if let Some(host) = owner.hostname.as_deref() {
+        if hostname().is_some_and(|local| local != host) {
+            return Some(Foreign::Host(host.to_owned()));
+        }
+    }
+
+    owner.uid.filter(|owner| *owner != uid()).map(Foreign::User)
+}
+
+/// Identifies the owner through /proc, nothing is returned when the process runs on another
+/// host, belongs to another user or already exited.
+pub fn resolve(owner: &Owner) -> Option<Requester> {
+    if foreign(owner).is_some() {
+        return None;
+    }
+
+    let proc = PathBuf::from(format!("/proc/{}", owner.pid));
+    // The pid may have been reused by a process of someone else.
+    let uid = std::fs::metadata(&proc).ok()?.uid();
+    if owner.uid.is_some_and(|expected| expected != uid) {
+        return None;
+    }
+
+    let executable = std::fs::read_link(proc.join("exe")).ok()?;
+    let command_line = std::fs::read(proc.join("cmdline"))
+        .map(|cmdline| {
+            cmdline
+                .split(|b| *b == 0)
+                .filter(|arg| !arg.is_empty())
+                .map(|arg| String::from_utf8_lossy(arg).into_owned())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let program = executable.file_name()?.to_string_lossy().into_owned();
+    let entry = applications_dirs()
+        .iter()
+        .find_map(|dir| find_desktop_entry(dir, &program));
+
+    Some(match entry {
+        Some(entry) => Requester {
+            name: entry.name,
+            icon: entry.icon.or(Some(program)),
+            executable,
+            command_line,
+        },
+        None => Requester {
+            name: program.clone(),
+            icon: Some(program),
+            executable,
+            command_line,
+        },
+    })
+}
+
+/// Name of the host running zuul.
+pub fn hostname() -> Option<String> {
+    let mut buf = [0u8; 256];
+    // SAFETY: `buf` is valid for its whole length.
+    if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } != 0 {
+        return None;
+    }
+
+    let len = buf.iter().position(|b| *b == 0)?;
+    String::from_utf8(buf[..len].to_vec()).ok()
+}
+
+/// User running zuul.
+pub fn uid() -> u32 {
+    // SAFETY: getuid takes no argument and can't fail.
+    unsafe { libc::getuid() }
+}
+
+#[derive(Debug, PartialEq)]
+struct DesktopEntry {
+    name: String,
+    icon: Option<String>,
+    exec: Option<String>,
+}
+
+// Directories holding the desktop entries, by order of precedence.
+fn applications_dirs() -> Vec<PathBuf> {
+    let data_home = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")));
+    let data_dirs = std::env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|dirs| !dirs.is_empty())
+        .unwrap_or_else(|| String::from("/usr/local/share:/usr/share"));
+
+    data_home
+        .into_iter()
+        .chain(data_dirs.split(':').map(PathBuf::from))
+        .map(|dir| dir.join("applications"))
+        .collect()
+}
+
+// Finds the desktop entry launching the program, either named after it or executing it.
+fn find_desktop_entry(dir: &Path, program: &str) -> Option<DesktopEntry> {
+    let named = dir.join(format!("{program}.desktop"));
+    if let Some(entry) = std::fs::read_to_string(named)
+        .ok()
+        .and_then(|c| parse_desktop_entry(&c))
+    {
+        return Some(entry);
+    }
+
+    std::fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .map(|e| e.path())
+        .filter(|path| path.extension() == Some(OsStr::new("desktop")))
+        .filter_map(|path| parse_desktop_entry(&std::fs::read_to_string(path).ok()?))
+        .find(|entry| entry.executes(program))
+}
+
+impl DesktopEntry {
+    fn executes(&self, program: &str) -> bool {
+        self.exec
+            .as_deref()
+            .and_then(|exec| exec.split_whitespace().next())
+            .and_then(|command| Path::new(command.trim_matches('"')).file_name())
+            .is_some_and(|name| name == program)
+    }
+}
+
+// Only the keys of the main group are read, the localized names are ignored.
+fn parse_desktop_entry(contents: &str) -> Option<DesktopEntry> {
+    let mut in_main_group = false;
+    let mut name = None;
+    let mut icon = None;
+    let mut exec = None;
+    let mut hidden = false;
+
+    for line in contents.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_main_group = line == "[Desktop Entry]";
+            continue;
+        }
+        if !in_main_group {
+            continue;
+        }
+
+        match line.split_once('=').map(|(k, v)| (k.trim(), v.trim())) {
+            Some(("Name", v)) => name = Some(v.to_owned()),
+            Some(("Icon", v)) if !v.is_empty() => icon = Some(v.to_owned()),
+            Some(("Exec", v)) => exec = Some(v.to_owned()),
+            Some(("Hidden", "true")) => hidden = true,
+            _ => {}
+        }
+    }
+
+    (!hidden).then_some(DesktopEntry {
+        name: name?,
+        icon,
+        exec,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_entry() {
+        let contents = "\
+[Desktop Entry]
+Type=Application
+Name=Thunderbird
+Name[fr]=Oiseau
+Icon=org.mozilla.Thunderbird
+Exec=thunderbird %u
+
+[Desktop Action ComposeMessage]
+Name=Write new message
+Exec=thunderbird -compose
+";
+        let entry = parse_desktop_entry(contents).unwrap();
+        assert_eq!("Thunderbird", entry.name);
+        assert_eq!(Some("org.mozilla.Thunderbird"), entry.icon.as_deref());
+        assert!(entry.executes("thunderbird"));
+        assert!(!entry.executes("firefox"));
+    }
+
+    #[test]
+    fn parse_entry_with_absolute_exec() {
+        let contents = "[Desktop Entry]\nName=Git GUI\nExec=\"/usr/bin/git\" gui\n";
+        assert!(parse_desktop_entry(contents).unwrap().executes("git"));
+    }
+
+    #[test]
+    fn parse_hidden_entry() {
+        let contents = "[Desktop Entry]\nName=Hidden\nHidden=true\n";
+        assert_eq!(None, parse_desktop_entry(contents));
+    }
+
+    #[test]
+    fn parse_entry_without_name() {
+        assert_eq!(None, parse_desktop_entry("[Desktop Entry]\nExec=true\n"));
+    }
+
+    #[test]
+    fn local_owner_is_not_foreign() {
+        let owner = Owner {
+            pid: 1,
+            uid: Some(uid()),
+            hostname: hostname(),
+        };
+        assert_eq!(None, foreign(&owner));
+    }
+
+    #[test]
+    fn owner_on_another_host_is_foreign() {
+        let remote = format!("not-{}", hostname().unwrap_or_default());
+        let owner = Owner {
+            pid: 1,
+            uid: None,
+            hostname: Some(remote.clone()),
+        };
+        assert_eq!(Some(Foreign::Host(remote)), foreign(&owner));
+    }
+
+    #[test]
+    fn local_host_without_uid_is_not_foreign() {
+        let owner = Owner {
+            pid: 1,
+            uid: None,
+            hostname: hostname(),
+        };
+        assert_eq!(None, foreign(&owner));
+    }
+
+    #[test]
+    fn owner_with_another_uid_is_foreign() {
+        let uid = uid().wrapping_add(1);
+        let owner = Owner {
+            pid: 1,
+            uid: Some(uid),
+            hostname: None,
+        };
+        assert_eq!(Some(Foreign::User(uid)), foreign(&owner));
+    }
+
+    #[test]
+    fn resolve_current_process() {
+        let owner = Owner {
+            pid: std::process::id(),
+            uid: Some(uid()),
+            hostname: hostname(),
+        };
+
+        let requester = resolve(&owner).unwrap();
+        assert_eq!(std::env::current_exe().unwrap(), requester.executable);
+        assert!(!requester.command_line.is_empty());
+    }
+
+    #[test]
+    fn resolve_process_of_another_user() {
+        let owner = Owner {
+            pid: std::process::id(),
+            uid: Some(uid().wrapping_add(1)),
+            hostname: None,
+        };
+        assert_eq!(None, resolve(&owner));
+    }
+
+    #[test]
+    fn resolve_process_on_another_host() {
+        let owner = Owner {
+            pid: std::process::id(),
+            uid: None,
+            hostname: Some(format!("not-{}", hostname().unwrap_or_default())),
+        };
+        assert_eq!(None, resolve(&owner));
+    }
+}
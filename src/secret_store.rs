@@ -0,0 +1,195 @@
+// SPDX-FileCopyrightText: 2025 Pier-Hugues Pellerin <ph@heykimo.com>
+//
+// SPDX-License-Identifier: MIT
+
+use std::ffi::{CString, c_long};
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+#[cfg(test)]
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+/// Storage of the passphrases the user asked zuul to remember, see
+/// `OPTION allow-external-password-cache`.
+pub trait SecretStore {
+    fn load(&self, keygrip: &str) -> std::io::Result<Option<String>>;
+    fn save(&mut self, keygrip: &str, passphrase: &str) -> std::io::Result<()>;
+    /// Removing a passphrase that isn't stored is not an error.
+    fn remove(&mut self, keygrip: &str) -> std::io::Result<()>;
+}
+
+/// Keeps the passphrases in the user keyring of the Linux kernel, they are never written to
+/// the disk. The keyring lives as long as a process of the user is running and lingering
+/// services keep it across logouts, so every passphrase expires `TIMEOUT` after it is
+/// saved.
+#[derive(Default, Clone, Copy)]
+pub struct KernelKeyring;
+
+/// Matches the `max-cache-ttl` of gpg-agent.
+const TIMEOUT: Duration = Duration::from_secs(2 * 60 * 60);
+
+impl KernelKeyring {
+    fn description(keygrip: &str) -> std::io::Result<CString> {
+        CString::new(format!("zuul:{keygrip}")).map_err(|_| Error::from(ErrorKind::InvalidInput))
+    }
+
+    fn search(keygrip: &str) -> std::io::Result<Option<c_long>> {
+        let description = Self::description(keygrip)?;
+
+        // SAFETY: both strings are nul terminated and outlive the call.
+        let id = unsafe {
+            libc::syscall(
+                libc::SYS_keyctl,
+                libc::KEYCTL_SEARCH as c_long,
+                libc::KEY_SPEC_USER_KEYRING as c_long,
+                c"user".as_ptr(),
+                description.as_ptr(),
+                0 as c_long,
+            )
+        };
+
+        match id {
+            -1 => match Error::last_os_error() {
+                e if e.raw_os_error() == Some(libc::ENOKEY) => Ok(None),
+                e => Err(e),
+            },
+            id => Ok(Some(id)),
+        }
+    }
+}
+
+impl SecretStore for KernelKeyring {
+    fn load(&self, keygrip: &str) -> std::io::Result<Option<String>> {
+        let Some(id) = Self::search(keygrip)? else {
+            return Ok(None);
+        };
+
+        // The first call only returns the size of the payload.
+        // SAFETY: a null buffer of size 0 is allowed by KEYCTL_READ.
+        let size = unsafe {
+            libc::syscall(
+                libc::SYS_keyctl,
+                libc::KEYCTL_READ as c_long,
+                id,
+                std::ptr::null_mut::<u8>(),
+                0 as c_long,
+            )
+        };
+        if size < 0 {
+            return Err(Error::last_os_error());
+        }
+
+        let mut payload = vec![0u8; size as usize];
+        // SAFETY: `payload` is a valid buffer of `size` bytes.
+        let read = unsafe {
+            libc::syscall(
+                libc::SYS_keyctl,
+                libc::KEYCTL_READ as c_long,
+                id,
+                payload.as_mut_ptr(),
+                size,
+            )
+        };
+        if read < 0 {
+            return Err(Error::last_os_error());
+        }
+        payload.truncate(read.min(size) as usize);
+
+        String::from_utf8(payload).map(Some).map_err(|e| {
+            e.into_bytes().fill(0);
+            Error::from(ErrorKind::InvalidData)
+        })
+    }
+
+    fn save(&mut self, keygrip: &str, passphrase: &str) -> std::io::Result<()> {
+        let description = Self::description(keygrip)?;
+
+        // An existing key with the same description is updated.
+        // SAFETY: both strings are nul terminated and the payload is valid for its length.
+        let id = unsafe {
+            libc::syscall(
+                libc::SYS_add_key,
+                c"user".as_ptr(),
+                description.as_ptr(),
+                passphrase.as_ptr(),
+                passphrase.len(),
+                libc::KEY_SPEC_USER_KEYRING as c_long,
+            )
+        };
+
+        if id == -1 {
+            return Err(Error::last_os_error());
+        }
+
+        // The timeout is also set again when an existing key is updated.
+        // SAFETY: KEYCTL_SET_TIMEOUT only takes the id of the key and a number of seconds.
+        let timeout = unsafe {
+            libc::syscall(
+                libc::SYS_keyctl,
+                libc::KEYCTL_SET_TIMEOUT as c_long,
+                id,
+                TIMEOUT.as_secs() as c_long,
+            )
+        };
+
+        match timeout {
+            -1 => Err(Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+
+    fn remove(&mut self, keygrip: &str) -> std::io::Result<()> {
+        let Some(id) = Self::search(keygrip)? else {
+            return Ok(());
+        };
+
+        // SAFETY: KEYCTL_INVALIDATE only takes the id of the key.
+        match unsafe { libc::syscall(libc::SYS_keyctl, libc::KEYCTL_INVALIDATE as c_long, id) } {
+            -1 => Err(Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Keeps the passphrases in memory, for the tests.
+#[cfg(test)]
+#[derive(Default, Clone)]
+pub struct MemoryStore(pub Rc<RefCell<HashMap<String, String>>>);
+
+#[cfg(test)]
+impl SecretStore for MemoryStore {
+    fn load(&self, keygrip: &str) -> std::io::Result<Option<String>> {
+        Ok(self.0.borrow().get(keygrip).cloned())
+    }
+
+    fn save(&mut self, keygrip: &str, passphrase: &str) -> std::io::Result<()> {
+        self.0
+            .borrow_mut()
+            .insert(keygrip.to_string(), passphrase.to_string());
+        Ok(())
+    }
+
+    fn remove(&mut self, keygrip: &str) -> std::io::Result<()> {
+        self.0.borrow_mut().remove(keygrip);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Touches the real keyring of the user, run with `cargo test -- --ignored`.
+    #[test]
+    #[ignore]
+    fn kernel_keyring_round_trip() {
+        let keygrip = format!("test-{}", std::process::id());
+        let mut keyring = KernelKeyring;
+
+        keyring.save(&keygrip, "secret").unwrap();
+        assert_eq!(Some("secret".to_string()), keyring.load(&keygrip).unwrap());
+
+        keyring.remove(&keygrip).unwrap();
+        assert_eq!(None, keyring.load(&keygrip).unwrap());
+        keyring.remove(&keygrip).unwrap();
+    }
+}
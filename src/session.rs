@@ -0,0 +1,1985 @@
+// SPDX-FileCopyrightText: 2025 Pier-Hugues Pellerin <ph@heykimo.com>
+//
+// SPDX-License-Identifier: MIT
+
+use crate::form::{Form, apply_commands};
+use crate::keypad::Keypad;
+use crate::requester::{self, Foreign};
+use crate::secret_store::{KernelKeyring, SecretStore};
+use assuan::{Command, ErrorCode, Inquiry, Response};
+use std::borrow::Cow;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// How long the dialog is animated to draw the attention on a retry.
+const RETRY_CUE_DURATION: Duration = Duration::from_millis(600);
+
+/// How long the user must stop typing before the agent is asked about the passphrase quality.
+const QUALITY_DEBOUNCE: Duration = Duration::from_millis(250);
+
+/// Number of characters displayed together when the passphrase is formatted.
+const GROUP_SIZE: usize = 5;
+
+/// Separates the groups of a formatted passphrase, unlike a space it can't be typed by
+/// mistake so the passphrase may still contain spaces.
+const GROUP_SEPARATOR: char = '\u{a0}';
+
+/// Source of time used by the session, the tests replace it to control the timeout.
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+#[derive(Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Events emitted by the user interface.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Input {
+    PassphraseChange(String),
+    RepeatChange(String),
+    TogglePassphraseVisibility,
+    /// Answer to the question asked before revealing the passphrase.
+    ConfirmVisibility(bool),
+    /// The user agrees to answer a request coming from another host or user.
+    AcknowledgeForeign,
+    /// The passphrase is remembered once accepted, see `OPTION allow-external-password-cache`.
+    SavePassphrase(bool),
+    /// Asks the agent for a passphrase, see `SETGENPIN`.
+    GeneratePassphrase,
+    /// Keys of the on-screen keypad, they edit the passphrase and then its repetition.
+    KeypadDigit(char),
+    KeypadBackspace,
+    KeypadEnter,
+    Ok,
+    NotOk,
+    Cancel,
+    Tick,
+}
+
+/// The different ways the agent can go away.
+#[derive(Debug, Clone, Copy)]
+pub enum Disconnect {
+    /// stdin was closed.
+    Eof,
+    /// A reply couldn't be written to stdout.
+    BrokenOutput,
+    /// stdin couldn't be read or the agent sent something that isn't understood.
+    BrokenInput,
+}
+
+/// Actions that the front end needs to execute after the session processed an event.
+#[derive(Debug, PartialEq)]
+pub enum Effect {
+    Reply(Response),
+    Show,
+    Hide,
+    /// Updates the modification time of the file, see `OPTION touch-file`.
+    TouchFile(PathBuf),
+    /// Terminates zuul with the following status code, also documented in the README:
+    /// - `exitcode::OK` when the agent says BYE or closes stdin while no dialog is displayed.
+    /// - `exitcode::DATAERR` when a command from the agent can't be parsed.
+    /// - `exitcode::UNAVAILABLE` when the agent closes stdin while a dialog is displayed.
+    /// - `exitcode::IOERR` when a reply can't be written to the agent.
+    Exit(i32),
+}
+
+/// What the agent asked for.
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    /// GETPIN, ask for a passphrase.
+    #[default]
+    Passphrase,
+    /// CONFIRM, ask a question without any input.
+    Confirm { one_button: bool },
+    /// MESSAGE, display a notice until the user dismisses it.
+    Message,
+}
+
+/// Everything required to draw the dialog currently displayed.
+#[derive(Default, Clone, Debug)]
+pub struct Dialog {
+    mode: Mode,
+    form: Form,
+    passphrase: String,
+    repeat: String,
+    passphrase_is_visible: bool,
+    // The user is asked to confirm before the passphrase is revealed.
+    confirming_visibility: bool,
+    save_passphrase: bool,
+    foreign: Option<Foreign>,
+    // The dialog is locked until the user acknowledges the foreign request.
+    foreign_acknowledged: bool,
+    // Digits of the on-screen keypad, only present when a PIN is requested.
+    keypad: Option<[char; 10]>,
+    // The keypad edits the repetition once the user is done with the PIN.
+    keypad_on_repeat: bool,
+    grab: bool,
+    // Moment when the dialog is closed if the user didn't interact with it.
+    deadline: Option<Instant>,
+    // Moment when the retry animation ends.
+    retry_cue_until: Option<Instant>,
+    // Why the agent rejected the passphrase.
+    constraints_error: Option<String>,
+    // Quality of the passphrase, as rated by the agent.
+    quality: Option<i32>,
+    // Moment when the quality of the passphrase is inquired.
+    quality_due: Option<Instant>,
+    pending: Option<Pending>,
+    // Action of the user delayed until the agent answers the pending inquiry.
+    queued: Option<Input>,
+}
+
+/// Inquiry waiting for the agent's answer, along with the data received so far.
+#[derive(Clone, Debug)]
+struct Pending {
+    kind: InquiryKind,
+    data: String,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum InquiryKind {
+    Quality,
+    CheckPin,
+    GenPin,
+}
+
+impl Dialog {
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn form(&self) -> &Form {
+        &self.form
+    }
+
+    pub fn passphrase(&self) -> &str {
+        &self.passphrase
+    }
+
+    pub fn repeat(&self) -> &str {
+        &self.repeat
+    }
+
+    /// The passphrase as it's displayed, split in groups when it's formatted and visible.
+    pub fn displayed_passphrase(&self) -> Cow<str> {
+        self.display(&self.passphrase)
+    }
+
+    pub fn displayed_repeat(&self) -> Cow<str> {
+        self.display(&self.repeat)
+    }
+
+    /// Explains the grouping of the characters, only present while the formatting is visible.
+    pub fn formatted_passphrase_hint(&self) -> Option<Cow<str>> {
+        self.is_formatted()
+            .then(|| self.form.formatted_passphrase_hint())
+            .flatten()
+    }
+
+    pub fn passphrase_is_visible(&self) -> bool {
+        self.passphrase_is_visible
+    }
+
+    /// True when the PIN of a smartcard is requested.
+    pub fn is_pin(&self) -> bool {
+        self.mode == Mode::Passphrase && self.form.pin()
+    }
+
+    /// True when only digits can be typed. The PIN is only known to be numeric when the agent
+    /// names the card, the PIN guessed from the prompt of other cards may contain letters.
+    pub fn digits_only(&self) -> bool {
+        self.is_pin() && self.form.card().is_some()
+    }
+
+    /// True when the PIN contains something else than digits, the user is warned but the
+    /// characters are kept.
+    pub fn pin_has_non_digits(&self) -> bool {
+        self.is_pin() && !self.passphrase.chars().all(|c| c.is_ascii_digit())
+    }
+
+    /// Digits of the keys of the on-screen keypad, from the top left.
+    pub fn keypad(&self) -> Option<&[char; 10]> {
+        self.keypad.as_ref()
+    }
+
+    /// True when the keypad edits the repetition of the PIN.
+    pub fn keypad_on_repeat(&self) -> bool {
+        self.keypad_on_repeat
+    }
+
+    /// True when the keyboard is grabbed, as asked by the agent unless the user overrides it.
+    pub fn grab(&self) -> bool {
+        self.grab
+    }
+
+    /// Set when the request comes from another host or user, like an agent forwarded over SSH.
+    pub fn foreign(&self) -> Option<&Foreign> {
+        self.foreign.as_ref()
+    }
+
+    /// True while the dialog is locked until the user acknowledges the foreign request.
+    pub fn awaiting_acknowledgement(&self) -> bool {
+        self.foreign.is_some() && !self.foreign_acknowledged
+    }
+
+    pub fn save_passphrase(&self) -> bool {
+        self.save_passphrase
+    }
+
+    /// True while the user is asked to confirm that the passphrase can be revealed.
+    pub fn confirming_visibility(&self) -> bool {
+        self.confirming_visibility
+    }
+
+    /// True while the agent answers an inquiry, the user has to wait for it.
+    pub fn is_waiting_for_agent(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// False when the passphrase needs to be repeated and both entries differ.
+    pub fn passphrases_match(&self) -> bool {
+        self.form.repeat().is_none() || self.passphrase == self.repeat
+    }
+
+    pub fn constraints_error(&self) -> Option<&str> {
+        self.constraints_error.as_deref()
+    }
+
+    /// Quality of the passphrase from -100 to 100, a negative value means that the agent
+    /// won't accept it.
+    pub fn quality(&self) -> Option<i32> {
+        self.quality
+    }
+
+    // The keys of the keypad edit the entry they target like the keyboard would.
+    fn keypad_edit(&self, input: &Input) -> Option<Input> {
+        self.keypad?;
+        let mut entry = if self.keypad_on_repeat {
+            self.repeat.clone()
+        } else {
+            self.passphrase.clone()
+        };
+
+        match input {
+            Input::KeypadDigit(digit) => entry.push(*digit),
+            Input::KeypadBackspace => {
+                entry.pop();
+            }
+            _ => return None,
+        }
+
+        Some(if self.keypad_on_repeat {
+            Input::RepeatChange(entry)
+        } else {
+            Input::PassphraseChange(entry)
+        })
+    }
+
+    fn is_formatted(&self) -> bool {
+        self.form.formatted_passphrase() && self.passphrase_is_visible
+    }
+
+    fn display<'a>(&self, secret: &'a str) -> Cow<'a, str> {
+        if self.is_formatted() {
+            Cow::Owned(group(secret))
+        } else {
+            Cow::Borrowed(secret)
+        }
+    }
+
+    /// Message shown once the user started to repeat the passphrase and the entries differ.
+    pub fn repeat_error(&self) -> Option<Cow<str>> {
+        (!self.repeat.is_empty() && !self.passphrases_match()).then(|| self.form.repeat_error())
+    }
+}
+
+enum State {
+    Idle,
+    Prompt(Box<Dialog>),
+}
+
+/// Pinentry protocol logic, it doesn't know anything about how the dialog is drawn.
+pub struct Session<C = SystemClock, S = KernelKeyring> {
+    clock: C,
+    store: S,
+    commands: Vec<Command>,
+    state: State,
+    confirm_visibility: bool,
+    confirm_foreign: bool,
+    keypad: Keypad,
+    grab: Option<bool>,
+    // The last GETPIN was answered from the cache.
+    answered_from_cache: bool,
+}
+
+impl<C: Clock, S: SecretStore> Session<C, S> {
+    pub fn new(clock: C, store: S) -> Self {
+        Self {
+            clock,
+            store,
+            commands: Vec::new(),
+            state: State::Idle,
+            confirm_visibility: true,
+            confirm_foreign: false,
+            keypad: Keypad::Hidden,
+            grab: None,
+            answered_from_cache: false,
+        }
+    }
+
+    /// Asks the user to confirm before the passphrase is revealed, enabled by default.
+    pub fn with_visibility_confirmation(mut self, confirm: bool) -> Self {
+        self.confirm_visibility = confirm;
+        self
+    }
+
+    /// Requests from another host or user are always flagged, they can also require the user
+    /// to acknowledge them before anything can be typed.
+    pub fn with_foreign_confirmation(mut self, confirm: bool) -> Self {
+        self.confirm_foreign = confirm;
+        self
+    }
+
+    /// The keypad displayed when the PIN of a smartcard is requested.
+    pub fn with_keypad(mut self, keypad: Keypad) -> Self {
+        self.keypad = keypad;
+        self
+    }
+
+    /// Overrides `OPTION grab` and `no-grab` sent by the agent when set.
+    pub fn with_grab(mut self, grab: Option<bool>) -> Self {
+        self.grab = grab;
+        self
+    }
+
+    /// Greeting sent to the agent when the pinentry starts.
+    pub fn hello(&self) -> Vec<Effect> {
+        vec![Effect::Reply(Response::OkHello)]
+    }
+
+    pub fn dialog(&self) -> Option<&Dialog> {
+        match &self.state {
+            State::Prompt(dialog) => Some(dialog.as_ref()),
+            State::Idle => None,
+        }
+    }
+
+    /// Progress of the retry animation, from 0 to 1, while an error is displayed.
+    pub fn retry_cue(&self) -> Option<f32> {
+        let remaining = self
+            .dialog()?
+            .retry_cue_until?
+            .saturating_duration_since(self.clock.now());
+        (!remaining.is_zero())
+            .then(|| 1.0 - remaining.as_secs_f32() / RETRY_CUE_DURATION.as_secs_f32())
+    }
+
+    /// How often the front end needs to send `Input::Tick`, if at all.
+    pub fn tick_interval(&self) -> Option<Duration> {
+        let dialog = self.dialog()?;
+
+        if self.retry_cue().is_some() {
+            Some(Duration::from_millis(16))
+        } else if dialog.quality_due.is_some() {
+            Some(Duration::from_millis(50))
+        } else if dialog.deadline.is_some() {
+            Some(Duration::from_secs(1))
+        } else {
+            None
+        }
+    }
+
+    /// Time left before the timeout, along with the full timeout.
+    pub fn countdown(&self) -> Option<(Duration, Duration)> {
+        let dialog = self.dialog()?;
+        let remaining = dialog.deadline?.saturating_duration_since(self.clock.now());
+        Some((remaining, dialog.form.timeout()?))
+    }
+
+    pub fn command(&mut self, command: Command) -> Vec<Effect> {
+        match command {
+            Command::Bye => vec![Effect::Reply(Response::Ok), Effect::Exit(exitcode::OK)],
+            Command::GetPin => self.open(Mode::Passphrase),
+            Command::Confirm { one_button } => self.open(Mode::Confirm { one_button }),
+            Command::Message => self.open(Mode::Message),
+            Command::Data(data) => self.inquiry_data(&data),
+            Command::End => self.inquiry_end(),
+            Command::Cancel => self.inquiry_cancel(),
+            Command::ClearPassphrase(Some(key_info)) => {
+                if let Err(err) = self.store.remove(&key_info.keygrip) {
+                    warn!("can't remove the passphrase of {}: {err}", key_info.keygrip);
+                }
+                vec![Effect::Reply(Response::Ok)]
+            }
+            Command::ClearPassphrase(None) => vec![Effect::Reply(Response::Ok)],
+            _ => {
+                self.commands.push(command);
+                vec![Effect::Reply(Response::Ok)]
+            }
+        }
+    }
+
+    pub fn input(&mut self, mut input: Input) -> Vec<Effect> {
+        let State::Prompt(dialog) = &mut self.state else {
+            return Vec::new();
+        };
+
+        // Nothing else can be sent to the agent until it answers the inquiry.
+        if dialog.pending.is_some() && matches!(input, Input::Ok | Input::NotOk | Input::Cancel) {
+            dialog.queued = Some(input);
+            return Vec::new();
+        }
+
+        if dialog.awaiting_acknowledgement()
+            && !matches!(
+                input,
+                Input::AcknowledgeForeign | Input::Cancel | Input::Tick
+            )
+        {
+            return Vec::new();
+        }
+
+        // Going back from an empty repetition, or typing the PIN with the keyboard, edits the
+        // PIN again.
+        if matches!(input, Input::KeypadBackspace)
+            && dialog.keypad_on_repeat
+            && dialog.repeat.is_empty()
+        {
+            dialog.keypad_on_repeat = false;
+            return Vec::new();
+        }
+        if matches!(input, Input::PassphraseChange(_)) {
+            dialog.keypad_on_repeat = false;
+        }
+
+        // The separators added by the formatting are never part of the passphrase.
+        if dialog.is_formatted() {
+            let previous = match &input {
+                Input::RepeatChange(_) => &dialog.repeat,
+                _ => &dialog.passphrase,
+            };
+            if let Input::PassphraseChange(edited) | Input::RepeatChange(edited) = &mut input {
+                let ungrouped = ungroup(previous, edited);
+                wipe(edited);
+                *edited = ungrouped;
+            }
+        }
+
+        if let Some(edit) = dialog.keypad_edit(&input) {
+            input = edit;
+        }
+
+        if let Input::PassphraseChange(passphrase) | Input::RepeatChange(passphrase) = &mut input {
+            if dialog.digits_only() && !passphrase.chars().all(|c| c.is_ascii_digit()) {
+                wipe(passphrase);
+                return Vec::new();
+            }
+        }
+
+        match input {
+            Input::PassphraseChange(passphrase) => {
+                // The timeout only dismisses a dialog left alone, it stops at the first key.
+                dialog.deadline = None;
+                dialog.passphrase = passphrase;
+                dialog.constraints_error = None;
+                if dialog.form.quality_bar().is_some() {
+                    dialog.quality_due = Some(self.clock.now() + QUALITY_DEBOUNCE);
+                }
+                Vec::new()
+            }
+            Input::RepeatChange(repeat) => {
+                dialog.deadline = None;
+                dialog.repeat = repeat;
+                Vec::new()
+            }
+            Input::TogglePassphraseVisibility
+                if !dialog.passphrase_is_visible && self.confirm_visibility =>
+            {
+                dialog.confirming_visibility = true;
+                Vec::new()
+            }
+            Input::TogglePassphraseVisibility => {
+                dialog.passphrase_is_visible = !dialog.passphrase_is_visible;
+                Vec::new()
+            }
+            Input::AcknowledgeForeign => {
+                dialog.foreign_acknowledged = true;
+                Vec::new()
+            }
+            Input::SavePassphrase(save) => {
+                dialog.save_passphrase = save && dialog.form.password_manager().is_some();
+                Vec::new()
+            }
+            Input::ConfirmVisibility(confirmed) => {
+                dialog.passphrase_is_visible |= dialog.confirming_visibility && confirmed;
+                dialog.confirming_visibility = false;
+                Vec::new()
+            }
+            Input::GeneratePassphrase
+                if dialog.pending.is_some() || dialog.form.gen_pin().is_none() =>
+            {
+                Vec::new()
+            }
+            Input::GeneratePassphrase => {
+                dialog.pending = Some(Pending {
+                    kind: InquiryKind::GenPin,
+                    data: String::new(),
+                });
+                vec![Effect::Reply(Response::Inquire(Inquiry::GenPin))]
+            }
+            // The keypad is hidden, or the key wasn't a digit.
+            Input::KeypadDigit(_) | Input::KeypadBackspace => Vec::new(),
+            Input::KeypadEnter if dialog.keypad.is_none() => Vec::new(),
+            Input::KeypadEnter if dialog.form.repeat().is_some() && !dialog.keypad_on_repeat => {
+                dialog.keypad_on_repeat = true;
+                Vec::new()
+            }
+            Input::KeypadEnter => self.input(Input::Ok),
+            Input::Ok if dialog.mode != Mode::Passphrase => {
+                let mut effects = self.dismiss();
+                effects.push(Effect::Reply(Response::Ok));
+                effects
+            }
+            Input::Ok if !dialog.passphrases_match() => Vec::new(),
+            // The passphrase is only accepted once the agent validated it.
+            Input::Ok if dialog.form.constraints_enforce() => {
+                dialog.pending = Some(Pending {
+                    kind: InquiryKind::CheckPin,
+                    data: String::new(),
+                });
+                let inquiry = Inquiry::CheckPin(dialog.passphrase.clone());
+                vec![Effect::Reply(Response::Inquire(inquiry))]
+            }
+            Input::Ok => self.accept(),
+            Input::NotOk if dialog.mode == Mode::Confirm { one_button: false } => {
+                self.close(ErrorCode::NotConfirmed)
+            }
+            Input::NotOk => Vec::new(),
+            // A message is only acknowledged, closing it is enough.
+            Input::Cancel if dialog.mode == Mode::Message => {
+                let mut effects = self.dismiss();
+                effects.push(Effect::Reply(Response::Ok));
+                effects
+            }
+            Input::Cancel => self.close(ErrorCode::Canceled),
+            // The timeout waits for the answer of any pending inquiry.
+            Input::Tick if dialog.pending.is_some() => Vec::new(),
+            Input::Tick => {
+                let now = self.clock.now();
+                if dialog.deadline.is_some_and(|deadline| now >= deadline) {
+                    self.close(ErrorCode::Timeout)
+                } else if dialog.quality_due.is_some_and(|due| now >= due) {
+                    dialog.quality_due = None;
+                    dialog.pending = Some(Pending {
+                        kind: InquiryKind::Quality,
+                        data: String::new(),
+                    });
+                    let inquiry = Inquiry::Quality(dialog.passphrase.clone());
+                    vec![Effect::Reply(Response::Inquire(inquiry))]
+                } else {
+                    Vec::new()
+                }
+            }
+        }
+    }
+
+    /// The agent is gone, nobody is left to answer so zuul has to stop.
+    pub fn disconnect(&mut self, reason: Disconnect) -> Vec<Effect> {
+        let was_displayed = self.clear().is_some();
+
+        let code = match reason {
+            Disconnect::Eof if was_displayed => exitcode::UNAVAILABLE,
+            Disconnect::Eof => exitcode::OK,
+            Disconnect::BrokenOutput => exitcode::IOERR,
+            Disconnect::BrokenInput => exitcode::DATAERR,
+        };
+
+        if was_displayed {
+            vec![Effect::Hide, Effect::Exit(code)]
+        } else {
+            vec![Effect::Exit(code)]
+        }
+    }
+
+    fn inquiry_data(&mut self, data: &str) -> Vec<Effect> {
+        match self.pending() {
+            Some(pending) => pending.data.push_str(data),
+            None => warn!("received data without any pending inquiry"),
+        }
+        Vec::new()
+    }
+
+    fn inquiry_end(&mut self) -> Vec<Effect> {
+        let State::Prompt(dialog) = &mut self.state else {
+            warn!("received END without any pending inquiry");
+            return Vec::new();
+        };
+        let Some(mut pending) = dialog.pending.take() else {
+            warn!("received END without any pending inquiry");
+            return Vec::new();
+        };
+
+        match pending.kind {
+            InquiryKind::Quality => {
+                dialog.quality = pending
+                    .data
+                    .trim()
+                    .parse::<i32>()
+                    .ok()
+                    .map(|q| q.clamp(-100, 100));
+            }
+            InquiryKind::CheckPin if pending.data.is_empty() => {
+                // The user may have canceled while the agent was checking the passphrase.
+                return match dialog.queued.take() {
+                    Some(Input::Cancel) => self.close(ErrorCode::Canceled),
+                    _ => self.accept(),
+                };
+            }
+            InquiryKind::CheckPin => dialog.constraints_error = Some(pending.data),
+            InquiryKind::GenPin if pending.data.is_empty() => {
+                warn!("the agent didn't generate any passphrase");
+            }
+            // Both entries are filled and the passphrase is revealed so the user can write it
+            // down.
+            InquiryKind::GenPin => {
+                wipe(&mut dialog.passphrase);
+                wipe(&mut dialog.repeat);
+                dialog.repeat = pending.data.clone();
+                dialog.passphrase = std::mem::take(&mut pending.data);
+                dialog.passphrase_is_visible = true;
+                dialog.deadline = None;
+                dialog.constraints_error = None;
+                if dialog.form.quality_bar().is_some() {
+                    dialog.quality_due = Some(self.clock.now());
+                }
+            }
+        }
+
+        self.replay_queued()
+    }
+
+    fn inquiry_cancel(&mut self) -> Vec<Effect> {
+        let State::Prompt(dialog) = &mut self.state else {
+            warn!("received CAN without any pending inquiry");
+            return Vec::new();
+        };
+        if dialog.pending.take().is_none() {
+            warn!("received CAN without any pending inquiry");
+            return Vec::new();
+        }
+
+        self.replay_queued()
+    }
+
+    fn pending(&mut self) -> Option<&mut Pending> {
+        match &mut self.state {
+            State::Prompt(dialog) => dialog.pending.as_mut(),
+            State::Idle => None,
+        }
+    }
+
+    // Executes the action of the user that was waiting for the end of the inquiry.
+    fn replay_queued(&mut self) -> Vec<Effect> {
+        let queued = match &mut self.state {
+            State::Prompt(dialog) => dialog.queued.take(),
+            State::Idle => None,
+        };
+
+        queued.map(|input| self.input(input)).unwrap_or_default()
+    }
+
+    // Sends the passphrase to the agent.
+    fn accept(&mut self) -> Vec<Effect> {
+        let State::Prompt(dialog) = &mut self.state else {
+            return Vec::new();
+        };
+
+        if let Some(key_info) = dialog.form.key_info().filter(|_| dialog.save_passphrase) {
+            if let Err(err) = self.store.save(&key_info.keygrip, &dialog.passphrase) {
+                warn!("can't save the passphrase of {}: {err}", key_info.keygrip);
+            }
+        }
+
+        let repeated = dialog.form.repeat().is_some();
+        let passphrase = std::mem::take(&mut dialog.passphrase);
+        let mut effects = self.dismiss();
+        if repeated {
+            effects.push(Effect::Reply(Response::Status("PIN_REPEATED".to_string())));
+        }
+        effects.push(Effect::Reply(Response::Data(passphrase)));
+        effects.push(Effect::Reply(Response::Ok));
+        effects
+    }
+
+    fn open(&mut self, mode: Mode) -> Vec<Effect> {
+        if let State::Prompt(_) = self.state {
+            warn!("a {mode:?} dialog was requested while one is already displayed");
+            // The agent waits for an answer to every command.
+            return vec![Effect::Reply(Response::Err(ErrorCode::Canceled))];
+        }
+
+        let form = apply_commands(&self.commands);
+        // SETREPEAT and SETERROR describe a single request, the agent sends them again.
+        self.commands
+            .retain(|c| !matches!(c, Command::SetRepeat(_) | Command::SetError(_)));
+
+        // A request from another host or user never gets a passphrase without the user
+        // seeing it, whatever the cache holds.
+        let foreign = form.owner().and_then(requester::foreign);
+        if mode == Mode::Passphrase && foreign.is_none() {
+            if let Some(passphrase) = self.cached(&form) {
+                self.answered_from_cache = true;
+                let touch = form
+                    .touch_file()
+                    .map(|path| Effect::TouchFile(path.to_path_buf()));
+                return touch
+                    .into_iter()
+                    .chain([
+                        Effect::Reply(Response::Status("PASSWORD_FROM_CACHE".to_string())),
+                        Effect::Reply(Response::Data(passphrase)),
+                        Effect::Reply(Response::Ok),
+                    ])
+                    .collect();
+            }
+        }
+
+        let now = self.clock.now();
+        let deadline = form.timeout().map(|t| now + t);
+        let retry_cue_until = form.error().map(|_| now + RETRY_CUE_DURATION);
+        let keypad = (mode == Mode::Passphrase && form.pin())
+            .then(|| self.keypad.layout())
+            .flatten();
+        let grab = self.grab.unwrap_or(form.grab());
+        self.state = State::Prompt(Box::new(Dialog {
+            mode,
+            form,
+            deadline,
+            retry_cue_until,
+            foreign,
+            foreign_acknowledged: !self.confirm_foreign,
+            keypad,
+            grab,
+            ..Default::default()
+        }));
+        vec![Effect::Show]
+    }
+
+    // The passphrase remembered for the key. The cache is only tried once, the agent asking
+    // again means the cached passphrase was wrong, and it is removed when the agent reports
+    // an error. A new passphrase being chosen is never read from the cache.
+    fn cached(&mut self, form: &Form) -> Option<String> {
+        let retry = std::mem::take(&mut self.answered_from_cache);
+        let keygrip = &form.key_info()?.keygrip;
+        if retry && form.error().is_some() {
+            if let Err(err) = self.store.remove(keygrip) {
+                warn!("can't remove the passphrase of {keygrip}: {err}");
+            }
+        }
+
+        if retry
+            || form.password_manager().is_none()
+            || form.error().is_some()
+            || form.repeat().is_some()
+        {
+            return None;
+        }
+
+        self.store.load(keygrip).unwrap_or_else(|err| {
+            warn!("can't load the passphrase of {keygrip}: {err}");
+            None
+        })
+    }
+
+    fn close(&mut self, code: ErrorCode) -> Vec<Effect> {
+        let mut effects = self.dismiss();
+        effects.push(Effect::Reply(Response::Err(code)));
+        effects
+    }
+
+    // Hides the dialog and updates the touch file, both happen before the reply is sent.
+    fn dismiss(&mut self) -> Vec<Effect> {
+        let Some(dialog) = self.clear() else {
+            return Vec::new();
+        };
+
+        let mut effects = vec![Effect::Hide];
+        if let Some(path) = dialog.form.touch_file() {
+            effects.push(Effect::TouchFile(path.to_path_buf()));
+        }
+        effects
+    }
+
+    // Go back to idle, wiping anything the user typed.
+    fn clear(&mut self) -> Option<Box<Dialog>> {
+        match std::mem::replace(&mut self.state, State::Idle) {
+            State::Prompt(mut dialog) => {
+                wipe(&mut dialog.passphrase);
+                wipe(&mut dialog.repeat);
+                if let Some(pending) = dialog.pending.as_mut() {
+                    wipe(&mut pending.data);
+                }
+                Some(dialog)
+            }
+            State::Idle => None,
+        }
+    }
+}
+
+/// Splits the passphrase in groups of characters separated by `GROUP_SEPARATOR`.
+fn group(passphrase: &str) -> String {
+    let mut grouped = String::with_capacity(passphrase.len() + passphrase.len() / GROUP_SIZE);
+    for (i, c) in passphrase.chars().enumerate() {
+        if i > 0 && i % GROUP_SIZE == 0 {
+            grouped.push(GROUP_SEPARATOR);
+        }
+        grouped.push(c);
+    }
+    grouped
+}
+
+/// Removes the separators `group` added to `passphrase` from `edited`, what the user made of
+/// the displayed passphrase. Only the characters around the change are compared with the
+/// displayed ones, what was typed or pasted is kept as is, even a no-break space.
+fn ungroup(passphrase: &str, edited: &str) -> String {
+    // The separators are `None`.
+    let mut displayed = Vec::with_capacity(passphrase.len() + passphrase.len() / GROUP_SIZE);
+    for (i, c) in passphrase.chars().enumerate() {
+        if i > 0 && i % GROUP_SIZE == 0 {
+            displayed.push(None);
+        }
+        displayed.push(Some(c));
+    }
+    let edited: Vec<char> = edited.chars().collect();
+    let same = |d: &Option<char>, e: &char| d.unwrap_or(GROUP_SEPARATOR) == *e;
+
+    let prefix = displayed
+        .iter()
+        .zip(&edited)
+        .take_while(|(d, e)| same(d, e))
+        .count();
+    let suffix = displayed[prefix..]
+        .iter()
+        .rev()
+        .zip(edited[prefix..].iter().rev())
+        .take_while(|(d, e)| same(d, e))
+        .count();
+    let removed = &displayed[prefix..displayed.len() - suffix];
+    let inserted = &edited[prefix..edited.len() - suffix];
+
+    let mut kept = &displayed[..prefix];
+    // Deleting a separator deletes the character before it, like a backspace.
+    if inserted.is_empty() && !removed.is_empty() && removed.iter().all(Option::is_none) {
+        kept = &kept[..kept.len().saturating_sub(1)];
+    }
+
+    kept.iter()
+        .flatten()
+        .chain(inserted)
+        .chain(displayed[displayed.len() - suffix..].iter().flatten())
+        .collect()
+}
+
+/// Overwrites the memory used by a secret before releasing it.
+fn wipe(secret: &mut String) {
+    let mut bytes = std::mem::take(secret).into_bytes();
+    bytes.fill(0);
+    std::hint::black_box(&bytes);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::secret_store::MemoryStore;
+    use std::cell::Cell;
+    use std::convert::identity;
+    use std::rc::Rc;
+
+    #[derive(Clone)]
+    struct ManualClock(Rc<Cell<Instant>>);
+
+    impl ManualClock {
+        fn new() -> Self {
+            Self(Rc::new(Cell::new(Instant::now())))
+        }
+
+        fn advance(&self, d: Duration) {
+            self.0.set(self.0.get() + d);
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> Instant {
+            self.0.get()
+        }
+    }
+
+    type TestSession = Session<ManualClock, MemoryStore>;
+
+    // The session is configured by `configure`, then every command is expected to be
+    // acknowledged.
+    fn session_with(
+        commands: &[&str],
+        configure: impl FnOnce(TestSession) -> TestSession,
+    ) -> (TestSession, ManualClock) {
+        let clock = ManualClock::new();
+        let mut session = configure(Session::new(clock.clone(), MemoryStore::default()));
+        for command in commands {
+            let effects = session.command(Command::try_from(*command).unwrap());
+            assert_eq!(vec![Effect::Reply(Response::Ok)], effects);
+        }
+        (session, clock)
+    }
+
+    #[test]
+    fn getpin_shows_the_dialog() {
+        let (mut session, _) = session_with(&["SETPROMPT Passphrase:"], identity);
+
+        assert_eq!(vec![Effect::Show], session.command(Command::GetPin));
+        assert_eq!(
+            "Passphrase:",
+            session.dialog().unwrap().form().prompt().as_ref()
+        );
+    }
+
+    #[test]
+    fn second_dialog_is_refused() {
+        let (mut session, _) = session_with(&[], identity);
+        session.command(Command::GetPin);
+        session.input(Input::PassphraseChange("secret".to_string()));
+
+        assert_eq!(
+            vec![Effect::Reply(Response::Err(ErrorCode::Canceled))],
+            session.command(Command::Confirm { one_button: false })
+        );
+        let dialog = session.dialog().unwrap();
+        assert_eq!(Mode::Passphrase, dialog.mode());
+        assert_eq!("secret", dialog.passphrase());
+    }
+
+    #[test]
+    fn ok_sends_the_passphrase() {
+        let (mut session, _) = session_with(&[], identity);
+        session.command(Command::GetPin);
+        session.input(Input::PassphraseChange("secret".to_string()));
+
+        assert_eq!(
+            vec![
+                Effect::Hide,
+                Effect::Reply(Response::Data("secret".to_string())),
+                Effect::Reply(Response::Ok),
+            ],
+            session.input(Input::Ok)
+        );
+        assert!(session.dialog().is_none());
+    }
+
+    #[test]
+    fn ok_is_blocked_until_the_passphrases_match() {
+        let (mut session, _) =
+            session_with(&["SETREPEAT Repeat:", "SETREPEATERROR no match"], identity);
+        session.command(Command::GetPin);
+        session.input(Input::PassphraseChange("secret".to_string()));
+        session.input(Input::RepeatChange("secre".to_string()));
+
+        let dialog = session.dialog().unwrap();
+        assert_eq!("Repeat:", dialog.form().repeat().unwrap().as_ref());
+        assert_eq!("no match", dialog.repeat_error().unwrap().as_ref());
+        assert!(session.input(Input::Ok).is_empty());
+
+        session.input(Input::RepeatChange("secret".to_string()));
+        assert_eq!(
+            vec![
+                Effect::Hide,
+                Effect::Reply(Response::Status("PIN_REPEATED".to_string())),
+                Effect::Reply(Response::Data("secret".to_string())),
+                Effect::Reply(Response::Ok),
+            ],
+            session.input(Input::Ok)
+        );
+    }
+
+    #[test]
+    fn repeat_only_applies_to_the_next_getpin() {
+        let (mut session, _) = session_with(&["SETREPEAT"], identity);
+        session.command(Command::GetPin);
+        session.input(Input::Cancel);
+        session.command(Command::GetPin);
+
+        assert!(session.dialog().unwrap().form().repeat().is_none());
+    }
+
+    #[test]
+    fn error_is_displayed_with_a_retry_cue() {
+        let (mut session, clock) =
+            session_with(&["SETERROR Bad Passphrase (try 2 of 3)"], identity);
+        session.command(Command::GetPin);
+
+        assert_eq!(
+            "Bad Passphrase (try 2 of 3)",
+            session.dialog().unwrap().form().error().unwrap().as_ref()
+        );
+        assert_eq!(Some(0.0), session.retry_cue());
+
+        clock.advance(RETRY_CUE_DURATION);
+        assert!(session.retry_cue().is_none());
+
+        session.input(Input::Cancel);
+        session.command(Command::GetPin);
+        assert!(session.dialog().unwrap().form().error().is_none());
+    }
+
+    #[test]
+    fn cancel_replies_with_an_error() {
+        let (mut session, _) = session_with(&[], identity);
+        session.command(Command::GetPin);
+
+        assert_eq!(
+            vec![
+                Effect::Hide,
+                Effect::Reply(Response::Err(ErrorCode::Canceled))
+            ],
+            session.input(Input::Cancel)
+        );
+    }
+
+    #[test]
+    fn confirm_replies_ok() {
+        let (mut session, _) = session_with(&["SETNOTOK No"], identity);
+
+        assert_eq!(
+            vec![Effect::Show],
+            session.command(Command::Confirm { one_button: false })
+        );
+        assert_eq!(
+            Mode::Confirm { one_button: false },
+            session.dialog().unwrap().mode()
+        );
+        assert_eq!(
+            vec![Effect::Hide, Effect::Reply(Response::Ok)],
+            session.input(Input::Ok)
+        );
+    }
+
+    #[test]
+    fn confirm_not_ok_replies_not_confirmed() {
+        let (mut session, _) = session_with(&["SETNOTOK No"], identity);
+        session.command(Command::Confirm { one_button: false });
+
+        assert_eq!(
+            vec![
+                Effect::Hide,
+                Effect::Reply(Response::Err(ErrorCode::NotConfirmed))
+            ],
+            session.input(Input::NotOk)
+        );
+    }
+
+    #[test]
+    fn confirm_one_button_ignores_not_ok() {
+        let (mut session, _) = session_with(&[], identity);
+        session.command(Command::Confirm { one_button: true });
+
+        assert!(session.input(Input::NotOk).is_empty());
+        assert_eq!(
+            vec![
+                Effect::Hide,
+                Effect::Reply(Response::Err(ErrorCode::Canceled))
+            ],
+            session.input(Input::Cancel)
+        );
+    }
+
+    #[test]
+    fn message_replies_ok_once_dismissed() {
+        let (mut session, _) = session_with(&["SETDESC Please insert the card"], identity);
+
+        assert_eq!(vec![Effect::Show], session.command(Command::Message));
+        assert_eq!(Mode::Message, session.dialog().unwrap().mode());
+        assert_eq!(
+            vec![Effect::Hide, Effect::Reply(Response::Ok)],
+            session.input(Input::Cancel)
+        );
+    }
+
+    #[test]
+    fn quality_is_inquired_once_the_user_stops_typing() {
+        let (mut session, clock) = session_with(&["SETQUALITYBAR"], identity);
+        session.command(Command::GetPin);
+        session.input(Input::PassphraseChange("sec".to_string()));
+        session.input(Input::PassphraseChange("secret".to_string()));
+        assert!(session.input(Input::Tick).is_empty());
+
+        clock.advance(QUALITY_DEBOUNCE);
+        assert_eq!(
+            vec![Effect::Reply(Response::Inquire(Inquiry::Quality(
+                "secret".to_string()
+            )))],
+            session.input(Input::Tick)
+        );
+
+        assert!(session.command(Command::Data("-42".to_string())).is_empty());
+        assert!(session.command(Command::End).is_empty());
+        assert_eq!(Some(-42), session.dialog().unwrap().quality());
+    }
+
+    #[test]
+    fn ok_waits_for_the_end_of_the_inquiry() {
+        let (mut session, clock) = session_with(&["SETQUALITYBAR"], identity);
+        session.command(Command::GetPin);
+        session.input(Input::PassphraseChange("secret".to_string()));
+        clock.advance(QUALITY_DEBOUNCE);
+        session.input(Input::Tick);
+
+        assert!(session.input(Input::Ok).is_empty());
+        session.command(Command::Data("80".to_string()));
+        assert_eq!(
+            vec![
+                Effect::Hide,
+                Effect::Reply(Response::Data("secret".to_string())),
+                Effect::Reply(Response::Ok),
+            ],
+            session.command(Command::End)
+        );
+    }
+
+    #[test]
+    fn generated_passphrase_fills_both_entries() {
+        let (mut session, _) = session_with(&["SETGENPIN Suggest", "SETREPEAT"], identity);
+        session.command(Command::GetPin);
+
+        assert_eq!(
+            vec![Effect::Reply(Response::Inquire(Inquiry::GenPin))],
+            session.input(Input::GeneratePassphrase)
+        );
+        session.command(Command::Data("correct horse".to_string()));
+        assert!(session.command(Command::End).is_empty());
+
+        let dialog = session.dialog().unwrap();
+        assert_eq!("correct horse", dialog.passphrase());
+        assert_eq!("correct horse", dialog.repeat());
+        assert!(dialog.passphrase_is_visible());
+    }
+
+    #[test]
+    fn generate_passphrase_requires_setgenpin() {
+        let (mut session, _) = session_with(&[], identity);
+        session.command(Command::GetPin);
+
+        assert!(session.input(Input::GeneratePassphrase).is_empty());
+    }
+
+    #[test]
+    fn formatted_passphrase_is_grouped_while_visible() {
+        let (mut session, _) = session_with(
+            &[
+                "OPTION formatted-passphrase",
+                "OPTION formatted-passphrase-hint=Blocks of five",
+            ],
+            identity,
+        );
+        session.command(Command::GetPin);
+        session.input(Input::PassphraseChange("abcdefghijkl".to_string()));
+
+        let dialog = session.dialog().unwrap();
+        assert_eq!("abcdefghijkl", dialog.displayed_passphrase());
+        assert_eq!(None, dialog.formatted_passphrase_hint());
+
+        session.input(Input::TogglePassphraseVisibility);
+        session.input(Input::ConfirmVisibility(true));
+        let dialog = session.dialog().unwrap();
+        assert_eq!("abcde\u{a0}fghij\u{a0}kl", dialog.displayed_passphrase());
+        assert_eq!(
+            Some("Blocks of five"),
+            dialog.formatted_passphrase_hint().as_deref()
+        );
+    }
+
+    #[test]
+    fn formatted_passphrase_is_submitted_without_separators() {
+        let (mut session, _) = session_with(&["OPTION formatted-passphrase"], identity);
+        session.command(Command::GetPin);
+        session.input(Input::PassphraseChange("abcdefghij".to_string()));
+        session.input(Input::TogglePassphraseVisibility);
+        session.input(Input::ConfirmVisibility(true));
+        session.input(Input::PassphraseChange("abcde\u{a0}fghijk".to_string()));
+        assert_eq!(
+            "abcde\u{a0}fghij\u{a0}k",
+            session.dialog().unwrap().displayed_passphrase()
+        );
+
+        assert_eq!(
+            vec![
+                Effect::Hide,
+                Effect::Reply(Response::Data("abcdefghijk".to_string())),
+                Effect::Reply(Response::Ok),
+            ],
+            session.input(Input::Ok)
+        );
+    }
+
+    #[test]
+    fn formatted_passphrase_keeps_its_spaces() {
+        let (mut session, _) = session_with(&["OPTION formatted-passphrase"], identity);
+        session.command(Command::GetPin);
+        session.input(Input::PassphraseChange("correct horse".to_string()));
+        assert_eq!("correct horse", session.dialog().unwrap().passphrase());
+
+        session.input(Input::TogglePassphraseVisibility);
+        session.input(Input::ConfirmVisibility(true));
+        session.input(Input::PassphraseChange(
+            "corre\u{a0}ct ho\u{a0}rse battery".to_string(),
+        ));
+
+        assert_eq!(
+            vec![
+                Effect::Hide,
+                Effect::Reply(Response::Data("correct horse battery".to_string())),
+                Effect::Reply(Response::Ok),
+            ],
+            session.input(Input::Ok)
+        );
+    }
+
+    #[test]
+    fn formatted_passphrase_keeps_a_typed_no_break_space() {
+        let (mut session, _) = session_with(&["OPTION formatted-passphrase"], identity);
+        session.command(Command::GetPin);
+        session.input(Input::TogglePassphraseVisibility);
+        session.input(Input::ConfirmVisibility(true));
+        session.input(Input::PassphraseChange("abc\u{a0}de".to_string()));
+        session.input(Input::PassphraseChange("abc\u{a0}d\u{a0}ef".to_string()));
+
+        assert_eq!("abc\u{a0}def", session.dialog().unwrap().passphrase());
+    }
+
+    #[test]
+    fn ungroup_only_removes_the_displayed_separators() {
+        assert_eq!("", ungroup("", ""));
+        assert_eq!("abcdefg", ungroup("abcdef", "abcde\u{a0}fg"));
+        assert_eq!("abxcdef", ungroup("abcdef", "abxcde\u{a0}f"));
+        assert_eq!("a\u{a0}b", ungroup("", "a\u{a0}b"));
+        assert_eq!("abc\u{a0}def", ungroup("abc\u{a0}de", "abc\u{a0}d\u{a0}ef"));
+        // The separator and the character before it are deleted together.
+        assert_eq!("abcdf", ungroup("abcdef", "abcdef"));
+        assert_eq!("abcde", ungroup("abcdef", "abcde\u{a0}"));
+        assert_eq!("xyz", ungroup("abcdefghijkl", "xyz"));
+    }
+
+    #[test]
+    fn group_by_five() {
+        assert_eq!("", group(""));
+        assert_eq!("abcde", group("abcde"));
+        assert_eq!("abcde\u{a0}f", group("abcdef"));
+        assert_eq!("éèàçù\u{a0}ü", group("éèàçùü"));
+    }
+
+    #[test]
+    fn default_labels_are_fallbacks() {
+        let (mut session, _) = session_with(
+            &[
+                "OPTION default-ok=Okay",
+                "OPTION default-cancel=Abort",
+                "OPTION default-prompt=Passphrase:",
+                "SETCANCEL Close",
+            ],
+            identity,
+        );
+        session.command(Command::GetPin);
+
+        let form = session.dialog().unwrap().form();
+        assert_eq!("Okay", form.button_ok());
+        assert_eq!("Close", form.button_cancel());
+        assert_eq!("Passphrase:", form.prompt());
+    }
+
+    #[test]
+    fn confirm_labels_use_default_yes_and_no() {
+        let (mut session, _) = session_with(
+            &[
+                "OPTION default-yes=Sure",
+                "OPTION default-no=Nope",
+                "SETNOTOK",
+            ],
+            identity,
+        );
+        session.command(Command::Confirm { one_button: false });
+
+        let form = session.dialog().unwrap().form();
+        assert_eq!("Sure", form.button_yes());
+        assert_eq!(Some("Nope"), form.button_not_ok().as_deref());
+    }
+
+    #[test]
+    fn revealing_the_passphrase_is_confirmed() {
+        let (mut session, _) = session_with(&[], identity);
+        session.command(Command::GetPin);
+
+        session.input(Input::TogglePassphraseVisibility);
+        let dialog = session.dialog().unwrap();
+        assert!(dialog.confirming_visibility());
+        assert!(!dialog.passphrase_is_visible());
+
+        session.input(Input::ConfirmVisibility(false));
+        let dialog = session.dialog().unwrap();
+        assert!(!dialog.confirming_visibility());
+        assert!(!dialog.passphrase_is_visible());
+
+        session.input(Input::TogglePassphraseVisibility);
+        session.input(Input::ConfirmVisibility(true));
+        assert!(session.dialog().unwrap().passphrase_is_visible());
+
+        // Hiding it again doesn't need any confirmation.
+        session.input(Input::TogglePassphraseVisibility);
+        assert!(!session.dialog().unwrap().passphrase_is_visible());
+    }
+
+    #[test]
+    fn visibility_confirmation_can_be_disabled() {
+        let (mut session, _) = session_with(&[], |s| s.with_visibility_confirmation(false));
+        session.command(Command::GetPin);
+
+        session.input(Input::TogglePassphraseVisibility);
+        let dialog = session.dialog().unwrap();
+        assert!(!dialog.confirming_visibility());
+        assert!(dialog.passphrase_is_visible());
+    }
+
+    const KEYGRIP: &str = "0123456789ABCDEF0123456789ABCDEF01234567";
+
+    const ALLOW_CACHE: &str = "OPTION allow-external-password-cache";
+    const KEY_INFO: &str = "SETKEYINFO n/0123456789ABCDEF0123456789ABCDEF01234567";
+
+    fn remember(session: &TestSession, passphrase: &str) {
+        session
+            .store
+            .0
+            .borrow_mut()
+            .insert(KEYGRIP.to_string(), passphrase.to_string());
+    }
+
+    #[test]
+    fn saved_passphrase_is_sent_from_the_cache() {
+        let (mut session, _) = session_with(&[ALLOW_CACHE, KEY_INFO], identity);
+        session.command(Command::GetPin);
+        session.input(Input::PassphraseChange("secret".to_string()));
+        session.input(Input::SavePassphrase(true));
+        session.input(Input::Ok);
+        assert_eq!(
+            Some("secret"),
+            session.store.0.borrow().get(KEYGRIP).map(String::as_str)
+        );
+
+        assert_eq!(
+            vec![
+                Effect::Reply(Response::Status("PASSWORD_FROM_CACHE".to_string())),
+                Effect::Reply(Response::Data("secret".to_string())),
+                Effect::Reply(Response::Ok),
+            ],
+            session.command(Command::GetPin)
+        );
+        assert!(session.dialog().is_none());
+    }
+
+    #[test]
+    fn passphrase_is_only_saved_when_asked() {
+        let (mut session, _) = session_with(&[ALLOW_CACHE, KEY_INFO], identity);
+        session.command(Command::GetPin);
+        session.input(Input::PassphraseChange("secret".to_string()));
+        session.input(Input::Ok);
+
+        assert!(session.store.0.borrow().is_empty());
+    }
+
+    #[test]
+    fn cache_requires_the_agent_permission() {
+        let (mut session, _) = session_with(&[KEY_INFO], identity);
+        remember(&session, "secret");
+        assert_eq!(vec![Effect::Show], session.command(Command::GetPin));
+        assert_eq!(None, session.dialog().unwrap().form().password_manager());
+    }
+
+    #[test]
+    fn cache_is_skipped_after_an_error() {
+        let (mut session, _) = session_with(
+            &[ALLOW_CACHE, KEY_INFO, "SETERROR Bad passphrase"],
+            identity,
+        );
+        remember(&session, "wrong");
+        assert_eq!(vec![Effect::Show], session.command(Command::GetPin));
+    }
+
+    #[test]
+    fn cached_answer_touches_the_file() {
+        let (mut session, _) = session_with(
+            &[
+                ALLOW_CACHE,
+                KEY_INFO,
+                "OPTION touch-file=/run/user/1000/gnupg/S.gpg-agent",
+            ],
+            identity,
+        );
+        remember(&session, "secret");
+
+        assert_eq!(
+            vec![
+                Effect::TouchFile(PathBuf::from("/run/user/1000/gnupg/S.gpg-agent")),
+                Effect::Reply(Response::Status("PASSWORD_FROM_CACHE".to_string())),
+                Effect::Reply(Response::Data("secret".to_string())),
+                Effect::Reply(Response::Ok),
+            ],
+            session.command(Command::GetPin)
+        );
+    }
+
+    #[test]
+    fn cache_is_only_tried_once() {
+        let (mut session, _) = session_with(&[ALLOW_CACHE, KEY_INFO], identity);
+        remember(&session, "secret");
+        assert_eq!(
+            vec![
+                Effect::Reply(Response::Status("PASSWORD_FROM_CACHE".to_string())),
+                Effect::Reply(Response::Data("secret".to_string())),
+                Effect::Reply(Response::Ok),
+            ],
+            session.command(Command::GetPin)
+        );
+
+        assert_eq!(vec![Effect::Show], session.command(Command::GetPin));
+        assert_eq!(1, session.store.0.borrow().len());
+    }
+
+    #[test]
+    fn error_after_a_cached_answer_removes_it() {
+        let (mut session, _) = session_with(&[ALLOW_CACHE, KEY_INFO], identity);
+        remember(&session, "wrong");
+        session.command(Command::GetPin);
+
+        session.command(Command::try_from("SETERROR Bad passphrase").unwrap());
+        assert_eq!(vec![Effect::Show], session.command(Command::GetPin));
+        assert!(session.store.0.borrow().is_empty());
+    }
+
+    #[test]
+    fn cache_is_skipped_for_a_new_passphrase() {
+        let (mut session, _) =
+            session_with(&[ALLOW_CACHE, KEY_INFO, "SETREPEAT Repeat:"], identity);
+        remember(&session, "secret");
+        assert_eq!(vec![Effect::Show], session.command(Command::GetPin));
+    }
+
+    #[test]
+    fn clear_passphrase_removes_it_from_the_cache() {
+        let (mut session, _) = session_with(&[], identity);
+        remember(&session, "secret");
+        let clear = format!("CLEARPASSPHRASE n/{KEYGRIP}");
+        assert_eq!(
+            vec![Effect::Reply(Response::Ok)],
+            session.command(Command::try_from(clear).unwrap())
+        );
+        assert!(session.store.0.borrow().is_empty());
+    }
+
+    #[test]
+    fn invalid_clear_passphrase_keeps_the_cache() {
+        let (mut session, _) = session_with(&[], identity);
+        remember(&session, "secret");
+        assert_eq!(
+            vec![Effect::Reply(Response::Ok)],
+            session.command(Command::try_from("CLEARPASSPHRASE hello").unwrap())
+        );
+        assert_eq!(1, session.store.0.borrow().len());
+    }
+
+    fn foreign_owner() -> String {
+        let uid = requester::uid().wrapping_add(1);
+        format!("OPTION owner=42/{uid}")
+    }
+
+    #[test]
+    fn foreign_request_is_flagged() {
+        let (mut session, _) = session_with(&[&foreign_owner()], identity);
+        session.command(Command::GetPin);
+
+        let dialog = session.dialog().unwrap();
+        assert!(matches!(dialog.foreign(), Some(Foreign::User(_))));
+        assert!(!dialog.awaiting_acknowledgement());
+    }
+
+    #[test]
+    fn unknown_owner_still_shows_the_dialog() {
+        let (mut session, _) = session_with(&["OPTION owner=me/1000 babayaga"], identity);
+        assert_eq!(vec![Effect::Show], session.command(Command::GetPin));
+        assert!(session.dialog().unwrap().foreign().is_none());
+    }
+
+    #[test]
+    fn foreign_request_skips_the_cache() {
+        let (mut session, _) = session_with(&[ALLOW_CACHE, KEY_INFO, &foreign_owner()], identity);
+        remember(&session, "secret");
+        assert_eq!(vec![Effect::Show], session.command(Command::GetPin));
+        assert!(session.dialog().unwrap().foreign().is_some());
+    }
+
+    #[test]
+    fn foreign_request_waits_for_acknowledgement() {
+        let (mut session, _) =
+            session_with(&[&foreign_owner()], |s| s.with_foreign_confirmation(true));
+        session.command(Command::GetPin);
+        assert!(session.dialog().unwrap().awaiting_acknowledgement());
+
+        session.input(Input::PassphraseChange("secret".to_string()));
+        assert!(session.input(Input::Ok).is_empty());
+        assert_eq!("", session.dialog().unwrap().passphrase());
+
+        session.input(Input::AcknowledgeForeign);
+        session.input(Input::PassphraseChange("secret".to_string()));
+        assert_eq!(
+            vec![
+                Effect::Hide,
+                Effect::Reply(Response::Data("secret".to_string())),
+                Effect::Reply(Response::Ok),
+            ],
+            session.input(Input::Ok)
+        );
+    }
+
+    #[test]
+    fn foreign_request_can_be_canceled() {
+        let (mut session, _) =
+            session_with(&[&foreign_owner()], |s| s.with_foreign_confirmation(true));
+        session.command(Command::GetPin);
+
+        assert_eq!(
+            vec![
+                Effect::Hide,
+                Effect::Reply(Response::Err(ErrorCode::Canceled)),
+            ],
+            session.input(Input::Cancel)
+        );
+    }
+
+    #[test]
+    fn keyboard_is_grabbed_by_default() {
+        let (mut session, _) = session_with(&[], identity);
+        session.command(Command::GetPin);
+        assert!(session.dialog().unwrap().grab());
+    }
+
+    #[test]
+    fn no_grab_applies_to_the_following_dialogs() {
+        let (mut session, _) = session_with(&["OPTION no-grab"], identity);
+        session.command(Command::GetPin);
+        assert!(!session.dialog().unwrap().form().grab());
+        session.input(Input::Cancel);
+
+        session.command(Command::GetPin);
+        assert!(!session.dialog().unwrap().form().grab());
+        session.input(Input::Cancel);
+
+        session.command(Command::try_from("OPTION grab").unwrap());
+        session.command(Command::GetPin);
+        assert!(session.dialog().unwrap().form().grab());
+    }
+
+    #[test]
+    fn grab_override_wins_over_the_agent() {
+        let (mut session, _) = session_with(&[], |s| s.with_grab(Some(false)));
+        session.command(Command::GetPin);
+        assert!(!session.dialog().unwrap().grab());
+        session.input(Input::Cancel);
+
+        let (mut session, _) = session_with(&["OPTION no-grab"], |s| s.with_grab(Some(true)));
+        session.command(Command::GetPin);
+        assert!(session.dialog().unwrap().grab());
+    }
+
+    const CARD: &str = "SETKEYINFO --D2760001240103040006123456780000";
+
+    #[test]
+    fn card_key_info_requests_a_pin() {
+        let (mut session, _) = session_with(&[CARD], identity);
+        session.command(Command::GetPin);
+
+        let dialog = session.dialog().unwrap();
+        assert!(dialog.is_pin());
+        assert_eq!(
+            Some("D2760001240103040006123456780000"),
+            dialog.form().card()
+        );
+    }
+
+    #[test]
+    fn scdaemon_prompt_requests_a_pin() {
+        for command in [
+            "SETPROMPT PIN",
+            "SETPROMPT Admin PIN:",
+            "SETDESC Please enter the PIN%0A[sigs done: 3]",
+        ] {
+            let (mut session, _) = session_with(&[command], identity);
+            session.command(Command::GetPin);
+            assert!(session.dialog().unwrap().is_pin(), "{command}");
+        }
+    }
+
+    #[test]
+    fn passphrase_is_not_a_pin() {
+        let (mut session, _) = session_with(
+            &[
+                "SETPROMPT Passphrase:",
+                "SETDESC Please enter the passphrase to unlock the OpenPGP secret key",
+            ],
+            identity,
+        );
+        session.command(Command::GetPin);
+
+        let dialog = session.dialog().unwrap();
+        assert!(!dialog.is_pin());
+        assert!(dialog.keypad().is_none());
+    }
+
+    #[test]
+    fn card_pin_only_accepts_digits() {
+        let (mut session, _) = session_with(&[CARD], identity);
+        session.command(Command::GetPin);
+
+        session.input(Input::PassphraseChange("1234".to_string()));
+        session.input(Input::PassphraseChange("1234a".to_string()));
+        assert_eq!("1234", session.dialog().unwrap().passphrase());
+    }
+
+    #[test]
+    fn guessed_pin_accepts_letters_with_a_warning() {
+        let (mut session, _) = session_with(&["SETPROMPT Admin PIN:"], identity);
+        session.command(Command::GetPin);
+        assert!(!session.dialog().unwrap().digits_only());
+
+        session.input(Input::PassphraseChange("1234".to_string()));
+        assert!(!session.dialog().unwrap().pin_has_non_digits());
+
+        session.input(Input::PassphraseChange("1234a".to_string()));
+        let dialog = session.dialog().unwrap();
+        assert_eq!("1234a", dialog.passphrase());
+        assert!(dialog.pin_has_non_digits());
+    }
+
+    #[test]
+    fn keypad_is_hidden_by_default() {
+        let (mut session, _) = session_with(&[CARD], identity);
+        session.command(Command::GetPin);
+        assert!(session.dialog().unwrap().keypad().is_none());
+
+        session.input(Input::KeypadDigit('1'));
+        assert_eq!("", session.dialog().unwrap().passphrase());
+    }
+
+    #[test]
+    fn keypad_is_only_displayed_for_a_pin() {
+        let (mut session, _) = session_with(&[], |s| s.with_keypad(Keypad::Ordered));
+        session.command(Command::GetPin);
+        assert!(session.dialog().unwrap().keypad().is_none());
+    }
+
+    #[test]
+    fn keypad_types_the_pin() {
+        let (mut session, _) = session_with(&[CARD], |s| s.with_keypad(Keypad::Ordered));
+        session.command(Command::GetPin);
+        assert_eq!(
+            Some(&['1', '2', '3', '4', '5', '6', '7', '8', '9', '0']),
+            session.dialog().unwrap().keypad()
+        );
+
+        for digit in ['1', '2', '3', '9'] {
+            session.input(Input::KeypadDigit(digit));
+        }
+        session.input(Input::KeypadBackspace);
+        session.input(Input::KeypadDigit('4'));
+        session.input(Input::KeypadDigit('x'));
+        assert_eq!("1234", session.dialog().unwrap().passphrase());
+
+        assert_eq!(
+            vec![
+                Effect::Hide,
+                Effect::Reply(Response::Data("1234".to_string())),
+                Effect::Reply(Response::Ok),
+            ],
+            session.input(Input::KeypadEnter)
+        );
+    }
+
+    #[test]
+    fn keypad_types_the_repetition() {
+        let (mut session, _) =
+            session_with(&[CARD, "SETREPEAT"], |s| s.with_keypad(Keypad::Shuffled));
+        session.command(Command::GetPin);
+
+        session.input(Input::KeypadDigit('1'));
+        session.input(Input::KeypadDigit('2'));
+        assert!(session.input(Input::KeypadEnter).is_empty());
+        assert!(session.dialog().unwrap().keypad_on_repeat());
+
+        session.input(Input::KeypadDigit('1'));
+        session.input(Input::KeypadDigit('3'));
+        assert!(session.input(Input::KeypadEnter).is_empty());
+        assert!(!session.dialog().unwrap().passphrases_match());
+
+        session.input(Input::KeypadBackspace);
+        session.input(Input::KeypadDigit('2'));
+        assert_eq!(
+            vec![
+                Effect::Hide,
+                Effect::Reply(Response::Status("PIN_REPEATED".to_string())),
+                Effect::Reply(Response::Data("12".to_string())),
+                Effect::Reply(Response::Ok),
+            ],
+            session.input(Input::KeypadEnter)
+        );
+    }
+
+    #[test]
+    fn keypad_goes_back_to_the_pin() {
+        let (mut session, _) =
+            session_with(&[CARD, "SETREPEAT"], |s| s.with_keypad(Keypad::Ordered));
+        session.command(Command::GetPin);
+
+        session.input(Input::KeypadDigit('1'));
+        session.input(Input::KeypadEnter);
+        session.input(Input::KeypadBackspace);
+        assert!(!session.dialog().unwrap().keypad_on_repeat());
+
+        session.input(Input::KeypadBackspace);
+        assert_eq!("", session.dialog().unwrap().passphrase());
+    }
+
+    #[test]
+    fn keyboard_moves_the_keypad_back_to_the_pin() {
+        let (mut session, _) =
+            session_with(&[CARD, "SETREPEAT"], |s| s.with_keypad(Keypad::Ordered));
+        session.command(Command::GetPin);
+
+        session.input(Input::KeypadDigit('1'));
+        session.input(Input::KeypadEnter);
+        assert!(session.dialog().unwrap().keypad_on_repeat());
+
+        session.input(Input::PassphraseChange("12".to_string()));
+        assert!(!session.dialog().unwrap().keypad_on_repeat());
+        session.input(Input::KeypadDigit('3'));
+        assert_eq!("123", session.dialog().unwrap().passphrase());
+    }
+
+    #[test]
+    fn constraints_rejection_is_displayed() {
+        let (mut session, _) = session_with(&["OPTION constraints-enforce"], identity);
+        session.command(Command::GetPin);
+        session.input(Input::PassphraseChange("weak".to_string()));
+
+        assert_eq!(
+            vec![Effect::Reply(Response::Inquire(Inquiry::CheckPin(
+                "weak".to_string()
+            )))],
+            session.input(Input::Ok)
+        );
+        session.command(Command::Data("Too short".to_string()));
+        assert!(session.command(Command::End).is_empty());
+        assert_eq!(
+            Some("Too short"),
+            session.dialog().unwrap().constraints_error()
+        );
+
+        session.input(Input::PassphraseChange("stronger".to_string()));
+        assert!(session.dialog().unwrap().constraints_error().is_none());
+    }
+
+    #[test]
+    fn constraints_accepted_sends_the_passphrase() {
+        let (mut session, _) = session_with(&["OPTION constraints-enforce"], identity);
+        session.command(Command::GetPin);
+        session.input(Input::PassphraseChange("strong".to_string()));
+        session.input(Input::Ok);
+
+        assert_eq!(
+            vec![
+                Effect::Hide,
+                Effect::Reply(Response::Data("strong".to_string())),
+                Effect::Reply(Response::Ok),
+            ],
+            session.command(Command::End)
+        );
+    }
+
+    #[test]
+    fn checking_constraints_waits_for_the_agent() {
+        let (mut session, _) = session_with(&["OPTION constraints-enforce"], identity);
+        session.command(Command::GetPin);
+        session.input(Input::PassphraseChange("secret".to_string()));
+        assert!(!session.dialog().unwrap().is_waiting_for_agent());
+
+        session.input(Input::Ok);
+        assert!(session.dialog().unwrap().is_waiting_for_agent());
+
+        session.command(Command::Data("Too short".to_string()));
+        session.command(Command::End);
+        assert!(!session.dialog().unwrap().is_waiting_for_agent());
+    }
+
+    #[test]
+    fn cancel_while_checking_constraints() {
+        let (mut session, _) = session_with(&["OPTION constraints-enforce"], identity);
+        session.command(Command::GetPin);
+        session.input(Input::PassphraseChange("strong".to_string()));
+        session.input(Input::Ok);
+        assert!(session.input(Input::Cancel).is_empty());
+
+        assert_eq!(
+            vec![
+                Effect::Hide,
+                Effect::Reply(Response::Err(ErrorCode::Canceled)),
+            ],
+            session.command(Command::End)
+        );
+    }
+
+    #[test]
+    fn timeout_closes_the_dialog() {
+        let (mut session, clock) = session_with(&["SETTIMEOUT 10"], identity);
+        session.command(Command::GetPin);
+
+        clock.advance(Duration::from_secs(4));
+        assert_eq!(
+            Some((Duration::from_secs(6), Duration::from_secs(10))),
+            session.countdown()
+        );
+        assert!(session.input(Input::Tick).is_empty());
+
+        clock.advance(Duration::from_secs(6));
+        assert_eq!(
+            vec![
+                Effect::Hide,
+                Effect::Reply(Response::Err(ErrorCode::Timeout))
+            ],
+            session.input(Input::Tick)
+        );
+    }
+
+    #[test]
+    fn typing_stops_the_timeout() {
+        let (mut session, clock) = session_with(&["SETTIMEOUT 10"], identity);
+        session.command(Command::GetPin);
+        session.input(Input::PassphraseChange("s".to_string()));
+
+        clock.advance(Duration::from_secs(20));
+        assert!(session.countdown().is_none());
+        assert!(session.input(Input::Tick).is_empty());
+    }
+
+    #[test]
+    fn bye_exits() {
+        let (mut session, _) = session_with(&[], identity);
+
+        assert_eq!(
+            vec![Effect::Reply(Response::Ok), Effect::Exit(exitcode::OK)],
+            session.command(Command::Bye)
+        );
+    }
+
+    #[test]
+    fn closing_the_dialog_touches_the_file() {
+        let (mut session, _) = session_with(
+            &["OPTION touch-file=/run/user/1000/gnupg/S.gpg-agent"],
+            identity,
+        );
+        session.command(Command::GetPin);
+
+        assert_eq!(
+            vec![
+                Effect::Hide,
+                Effect::TouchFile(PathBuf::from("/run/user/1000/gnupg/S.gpg-agent")),
+                Effect::Reply(Response::Err(ErrorCode::Canceled)),
+            ],
+            session.input(Input::Cancel)
+        );
+    }
+
+    #[test]
+    fn eof_while_idle_exits() {
+        let (mut session, _) = session_with(&[], identity);
+
+        assert_eq!(
+            vec![Effect::Exit(exitcode::OK)],
+            session.disconnect(Disconnect::Eof)
+        );
+    }
+
+    #[test]
+    fn eof_while_displayed_closes_the_dialog() {
+        let (mut session, _) = session_with(&[], identity);
+        session.command(Command::GetPin);
+        session.input(Input::PassphraseChange("secret".to_string()));
+
+        assert_eq!(
+            vec![Effect::Hide, Effect::Exit(exitcode::UNAVAILABLE)],
+            session.disconnect(Disconnect::Eof)
+        );
+        assert!(session.dialog().is_none());
+    }
+
+    #[test]
+    fn broken_output_exits_with_io_error() {
+        let (mut session, _) = session_with(&[], identity);
+
+        assert_eq!(
+            vec![Effect::Exit(exitcode::IOERR)],
+            session.disconnect(Disconnect::BrokenOutput)
+        );
+    }
+
+    #[test]
+    fn broken_input_closes_the_dialog() {
+        let (mut session, _) = session_with(&[], identity);
+        session.command(Command::GetPin);
+        session.input(Input::PassphraseChange("secret".to_string()));
+
+        assert_eq!(
+            vec![Effect::Hide, Effect::Exit(exitcode::DATAERR)],
+            session.disconnect(Disconnect::BrokenInput)
+        );
+        assert!(session.dialog().is_none());
+    }
+
+    // Every way zuul can stop, with the status documented in the README.
+    #[test]
+    fn exit_codes_match_the_documentation() {
+        let cases: [(&[Command], Disconnect, i32); 6] = [
+            (&[], Disconnect::Eof, 0),
+            (&[Command::GetPin], Disconnect::Eof, 69),
+            (&[], Disconnect::BrokenInput, 65),
+            (&[Command::GetPin], Disconnect::BrokenInput, 65),
+            (&[], Disconnect::BrokenOutput, 74),
+            (&[Command::GetPin], Disconnect::BrokenOutput, 74),
+        ];
+        for (commands, disconnect, code) in cases {
+            let (mut session, _) = session_with(&[], identity);
+            for command in commands {
+                session.command(command.clone());
+            }
+            let effects = session.disconnect(disconnect);
+            assert_eq!(Some(&Effect::Exit(code)), effects.last(), "{disconnect:?}");
+        }
+
+        let (mut session, _) = session_with(&[], identity);
+        assert_eq!(Some(&Effect::Exit(0)), session.command(Command::Bye).last());
+    }
+
+    #[test]
+    fn wipe_empties_the_secret() {
+        let mut secret = "secret".to_string();
+        wipe(&mut secret);
+        assert!(secret.is_empty());
+    }
+}
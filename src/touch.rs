@@ -0,0 +1,140 @@
+// SPDX-FileCopyrightText: 2025 Pier-Hugues Pellerin <ph@heykimo.com>
+//
+// SPDX-License-Identifier: MIT
+
+use crate::requester::uid;
+use std::fs::OpenOptions;
+use std::io::{Error, ErrorKind};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::path::Path;
+
+/// Updates the modification time of the file given by `OPTION touch-file`, the agent
+/// watches its socket to know that the pinentry is done with the dialog.
+///
+/// A missing file, a symbolic link or a file owned by another user is left untouched.
+pub fn touch(path: &Path) -> std::io::Result<()> {
+    // The agent socket can't be opened for reading or writing, only as a path. The owner is
+    // checked and the time updated on the same file, even if the path is replaced meanwhile.
+    let file = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_PATH | libc::O_NOFOLLOW | libc::O_CLOEXEC)
+        .open(path)?;
+    let metadata = file.metadata()?;
+
+    if metadata.file_type().is_symlink() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "file is a symbolic link",
+        ));
+    }
+
+    if metadata.uid() != uid() {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            "file is owned by another user",
+        ));
+    }
+
+    // `futimens` doesn't accept a file opened with O_PATH, an empty path does the same.
+    // SAFETY: the descriptor is open, the path is nul terminated and a null `times` sets both
+    // the access and modification times to the current time.
+    let updated = unsafe {
+        libc::utimensat(
+            file.as_raw_fd(),
+            c"".as_ptr(),
+            std::ptr::null(),
+            libc::AT_EMPTY_PATH,
+        )
+    };
+    match updated {
+        0 => Ok(()),
+        _ => Err(Error::last_os_error()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::File;
+    use std::time::{Duration, SystemTime};
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("zuul-{}-{name}", std::process::id()))
+    }
+
+    #[test]
+    fn touch_updates_the_modification_time() {
+        let path = temp_path("touch");
+        let file = File::create(&path).unwrap();
+        let past = SystemTime::now() - Duration::from_secs(3600);
+        file.set_modified(past).unwrap();
+
+        touch(&path).unwrap();
+
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(modified > past);
+    }
+
+    #[test]
+    fn touch_missing_file() {
+        let path = temp_path("missing");
+        assert_eq!(ErrorKind::NotFound, touch(&path).unwrap_err().kind());
+    }
+
+    #[test]
+    fn touch_symbolic_link() {
+        let target = temp_path("target");
+        let link = temp_path("link");
+        let file = File::create(&target).unwrap();
+        let past = SystemTime::now() - Duration::from_secs(3600);
+        file.set_modified(past).unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        let err = touch(&link).unwrap_err();
+
+        let modified = std::fs::metadata(&target).unwrap().modified().unwrap();
+        std::fs::remove_file(&link).unwrap();
+        std::fs::remove_file(&target).unwrap();
+        assert_eq!(ErrorKind::InvalidInput, err.kind());
+        assert_eq!(past, modified);
+    }
+
+    #[test]
+    fn touch_socket() {
+        let path = temp_path("socket");
+        let _listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        let created = std::fs::metadata(&path).unwrap().modified().unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+
+        touch(&path).unwrap();
+
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(modified > created);
+    }
+
+    #[test]
+    fn touch_file_of_another_user() {
+        // The file of another user is needed, root owns everything it creates.
+        let (path, created) = if uid() == 0 {
+            let path = temp_path("nobody");
+            File::create(&path).unwrap();
+            std::os::unix::fs::chown(&path, Some(65534), Some(65534)).unwrap();
+            (path, true)
+        } else {
+            (std::path::PathBuf::from("/"), false)
+        };
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+
+        let err = touch(&path).unwrap_err();
+
+        let unchanged = std::fs::metadata(&path).unwrap().modified().unwrap() == modified;
+        if created {
+            std::fs::remove_file(&path).unwrap();
+        }
+        assert_eq!(ErrorKind::PermissionDenied, err.kind());
+        assert!(unchanged);
+    }
+}
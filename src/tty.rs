@@ -0,0 +1,914 @@
+// SPDX-FileCopyrightText: 2025 Pier-Hugues Pellerin <ph@heykimo.com>
+//
+// SPDX-License-Identifier: MIT
+
+use crate::agent::reply;
+use crate::fl;
+use crate::mnemonic::Mnemonic;
+use crate::requester::Foreign;
+use crate::secret_store::{KernelKeyring, SecretStore};
+use crate::session::{Dialog, Disconnect, Effect, Input, Mode, Session, SystemClock};
+use crate::touch::touch;
+use assuan::Command;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, Error, ErrorKind, Read, Write};
+use std::mem::MaybeUninit;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::Once;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::time::Duration;
+use tracing::{error, warn};
+
+/// Terminal used when neither the agent nor the command line name one.
+const DEFAULT_TTY: &str = "/dev/tty";
+
+const CTRL_C: u8 = 0x03;
+const CTRL_D: u8 = 0x04;
+const BACKSPACE: u8 = 0x08;
+const CTRL_U: u8 = 0x15;
+const ESCAPE: u8 = 0x1b;
+const DELETE: u8 = 0x7f;
+const BELL: &str = "\x07";
+
+// Arrows, function keys and Alt combinations are sent as sequences starting with Escape,
+// their bytes arrive together. Escape alone cancels once nothing follows it for this long.
+const ESCAPE_DELAY: Duration = Duration::from_millis(50);
+
+/// Serves the agent on a terminal until it goes away, used when there is no graphical
+/// session like over SSH or on a console. Returns the exit code of zuul.
+pub fn run(session: Session, default_tty: Option<PathBuf>) -> i32 {
+    Tty {
+        session,
+        default_tty,
+        terminal: None,
+    }
+    .run()
+}
+
+/// Terminal given with `--ttyname`, `OPTION ttyname` takes precedence over it.
+pub fn ttyname_arg(args: impl IntoIterator<Item = String>) -> Option<PathBuf> {
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--ttyname" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(name) = arg.strip_prefix("--ttyname=") {
+            return Some(PathBuf::from(name));
+        }
+    }
+    None
+}
+
+struct Tty<S = KernelKeyring> {
+    session: Session<SystemClock, S>,
+    default_tty: Option<PathBuf>,
+    // Only open while a dialog is displayed.
+    terminal: Option<Terminal>,
+}
+
+/// What was read from the terminal.
+enum Key {
+    Byte(u8),
+    /// Ctrl+C, Ctrl+D or Escape alone, also when the terminal is gone.
+    Cancel,
+    /// The dialog timed out while waiting for the user.
+    Timeout(Vec<Effect>),
+}
+
+#[derive(Clone, Copy)]
+enum Entry {
+    Passphrase,
+    Repeat,
+}
+
+impl Entry {
+    fn change(self, value: String) -> Input {
+        match self {
+            Entry::Passphrase => Input::PassphraseChange(value),
+            Entry::Repeat => Input::RepeatChange(value),
+        }
+    }
+
+    fn value(self, dialog: &Dialog) -> &str {
+        match self {
+            Entry::Passphrase => dialog.passphrase(),
+            Entry::Repeat => dialog.repeat(),
+        }
+    }
+}
+
+impl<S: SecretStore> Tty<S> {
+    fn run(mut self) -> i32 {
+        let mut lines = std::io::stdin().lock().lines();
+        let mut effects = self.session.hello();
+
+        loop {
+            if let Some(code) = self.perform(effects) {
+                return code;
+            }
+
+            // The user is only asked while the agent isn't answering an inquiry.
+            effects = if self
+                .session
+                .dialog()
+                .is_some_and(|dialog| !dialog.is_waiting_for_agent())
+            {
+                self.prompt().unwrap_or_else(|err| {
+                    error!("can't use the terminal: {err}");
+                    self.session.input(Input::Cancel)
+                })
+            } else {
+                match lines.next() {
+                    Some(Ok(line)) => match Command::try_from(line) {
+                        Ok(command) => self.session.command(command),
+                        Err(err) => {
+                            error!("Error: {err}");
+                            self.session.disconnect(Disconnect::BrokenInput)
+                        }
+                    },
+                    Some(Err(_)) | None => self.session.disconnect(Disconnect::Eof),
+                }
+            };
+        }
+    }
+
+    // Executes the effects, the exit code is returned once zuul has to stop.
+    fn perform(&mut self, effects: Vec<Effect>) -> Option<i32> {
+        for effect in effects {
+            match effect {
+                Effect::Reply(response) => {
+                    if let Err(err) = reply(&response) {
+                        error!("Error: {err}");
+                        let effects = self.session.disconnect(Disconnect::BrokenOutput);
+                        return self.perform(effects);
+                    }
+                }
+                Effect::Show => {
+                    let path = self
+                        .session
+                        .dialog()
+                        .and_then(|dialog| dialog.form().tty_name())
+                        .or(self.default_tty.as_deref())
+                        .unwrap_or(Path::new(DEFAULT_TTY));
+                    self.terminal = Terminal::open(path)
+                        .inspect_err(|err| warn!("can't open `{}`: {err}", path.display()))
+                        .ok();
+                }
+                Effect::Hide => self.terminal = None,
+                Effect::TouchFile(path) => {
+                    if let Err(err) = touch(&path) {
+                        warn!("Can't touch `{}`: {err}", path.display());
+                    }
+                }
+                Effect::Exit(code) => return Some(code),
+            }
+        }
+        None
+    }
+
+    // Draws the dialog and waits for the user, it may still be displayed afterwards when the
+    // user has to try again.
+    fn prompt(&mut self) -> std::io::Result<Vec<Effect>> {
+        let Some(dialog) = self.session.dialog() else {
+            return Ok(Vec::new());
+        };
+        let text = render(dialog);
+        // Unlike the buttons of the dialog, Enter cancels the foreign request.
+        let acknowledge = dialog.awaiting_acknowledgement().then(|| {
+            vec![
+                (fl!("button-continue"), Input::AcknowledgeForeign),
+                (dialog.form().button_cancel().into_owned(), Input::Cancel),
+            ]
+        });
+        let mode = dialog.mode();
+        let choices = choices(dialog);
+
+        self.terminal()?.print(&text)?;
+        match (acknowledge, mode) {
+            (Some(acknowledge), _) => self.choose(acknowledge),
+            (None, Mode::Passphrase) => self.ask_passphrase(),
+            (None, Mode::Confirm { .. } | Mode::Message) => self.choose(choices),
+        }
+    }
+
+    fn ask_passphrase(&mut self) -> std::io::Result<Vec<Effect>> {
+        let Some(dialog) = self.session.dialog() else {
+            return Ok(Vec::new());
+        };
+        let prompt = printable(&dialog.form().prompt());
+        let repeat = dialog.form().repeat().map(|label| printable(&label));
+
+        if let Some(effects) = self.read_secret(&prompt, Entry::Passphrase)? {
+            return Ok(effects);
+        }
+
+        if let Some(label) = repeat {
+            if let Some(effects) = self.read_secret(&label, Entry::Repeat)? {
+                return Ok(effects);
+            }
+
+            // Both entries are typed again.
+            if let Some(error) = self.session.dialog().and_then(|d| d.repeat_error()) {
+                let error = format!("{}\n", printable(&error));
+                self.terminal()?.print(&error)?;
+                self.session.input(Input::PassphraseChange(String::new()));
+                self.session.input(Input::RepeatChange(String::new()));
+                return Ok(Vec::new());
+            }
+        }
+
+        Ok(self.session.input(Input::Ok))
+    }
+
+    // Reads an entry without echoing it, every change goes through the session so the
+    // characters it refuses, like letters in the PIN of a card, are never typed. Returns the
+    // effects when the dialog is closed before the user is done.
+    fn read_secret(&mut self, label: &str, entry: Entry) -> std::io::Result<Option<Vec<Effect>>> {
+        // A previous attempt may have been rejected by the agent.
+        if self
+            .session
+            .dialog()
+            .is_some_and(|dialog| !entry.value(dialog).is_empty())
+        {
+            self.session.input(entry.change(String::new()));
+        }
+
+        self.terminal()?.print(&format!("{label} "))?;
+
+        let mut typed = Vec::new();
+        let interrupted = loop {
+            match self.read_key() {
+                Ok(Key::Byte(b'\r' | b'\n')) => break None,
+                Ok(Key::Byte(BACKSPACE | DELETE)) => pop_char(&mut typed),
+                Ok(Key::Byte(CTRL_U)) => {
+                    typed.fill(0);
+                    typed.clear();
+                }
+                Ok(Key::Byte(b)) => typed.push(b),
+                Ok(Key::Cancel) => break Some(self.session.input(Input::Cancel)),
+                Ok(Key::Timeout(effects)) => break Some(effects),
+                Err(err) => {
+                    typed.fill(0);
+                    return Err(err);
+                }
+            }
+            // Characters are only sent once all their bytes are typed.
+            if let Ok(value) = std::str::from_utf8(&typed) {
+                self.session.input(entry.change(value.to_owned()));
+                if self
+                    .session
+                    .dialog()
+                    .is_some_and(|dialog| entry.value(dialog) != value)
+                {
+                    pop_char(&mut typed);
+                    self.terminal()?.print(BELL)?;
+                }
+            }
+        };
+        typed.fill(0);
+
+        if let Some(terminal) = self.terminal.as_mut() {
+            terminal.print("\n")?;
+        }
+        Ok(interrupted)
+    }
+
+    // Asks the user to pick one of the buttons with its mnemonic, Enter picks the last one.
+    fn choose(&mut self, choices: Vec<(String, Input)>) -> std::io::Result<Vec<Effect>> {
+        let mnemonics = choices
+            .iter()
+            .map(|(label, _)| Mnemonic::parse(label))
+            .collect::<Vec<_>>();
+        let question = mnemonics
+            .iter()
+            .map(|mnemonic| match shortcut(mnemonic) {
+                Some(key) => format!("{} ({})", mnemonic.text(), key.to_lowercase()),
+                None => mnemonic.text().to_owned(),
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        self.terminal()?
+            .print(&format!("[{}] ", printable(&question)))?;
+
+        let input = loop {
+            match self.read_key()? {
+                Key::Byte(b'\r' | b'\n') => match choices.last() {
+                    Some((_, input)) => break input.clone(),
+                    None => break Input::Cancel,
+                },
+                Key::Byte(b) => {
+                    let key = char::from(b);
+                    let chosen = mnemonics.iter().position(|mnemonic| {
+                        shortcut(mnemonic).is_some_and(|s| s.to_lowercase().eq(key.to_lowercase()))
+                    });
+                    match chosen {
+                        Some(i) => break choices[i].1.clone(),
+                        None => self.terminal()?.print(BELL)?,
+                    }
+                }
+                Key::Cancel => break Input::Cancel,
+                Key::Timeout(effects) => return Ok(effects),
+            }
+        };
+
+        self.terminal()?.print("\n")?;
+        Ok(self.session.input(input))
+    }
+
+    // Waits for the next key, the session is ticked so the dialog can time out.
+    fn read_key(&mut self) -> std::io::Result<Key> {
+        loop {
+            let timeout = self.session.countdown().map(|(remaining, _)| remaining);
+            let terminal = self.terminal()?;
+
+            if !terminal.poll(timeout)? {
+                let effects = self.session.input(Input::Tick);
+                if !effects.is_empty() {
+                    return Ok(Key::Timeout(effects));
+                }
+                continue;
+            }
+
+            return Ok(match terminal.read_byte()? {
+                None | Some(CTRL_C | CTRL_D) => Key::Cancel,
+                Some(ESCAPE) if !terminal.poll(Some(ESCAPE_DELAY))? => Key::Cancel,
+                Some(ESCAPE) => {
+                    terminal.skip_sequence()?;
+                    continue;
+                }
+                Some(b) => Key::Byte(b),
+            });
+        }
+    }
+
+    fn terminal(&mut self) -> std::io::Result<&mut Terminal> {
+        self.terminal
+            .as_mut()
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "no terminal"))
+    }
+}
+
+// Everything displayed above the input, in the same order as the graphical dialog.
+fn render(dialog: &Dialog) -> String {
+    let form = dialog.form();
+    let foreign = dialog.foreign().map(|foreign| match foreign {
+        Foreign::Host(host) => fl!("foreign-host-warning", host = host.as_str()),
+        Foreign::User(uid) => fl!("foreign-user-warning", uid = uid),
+    });
+
+    let mut text = String::from("\n");
+    for line in [
+        form.title().map(|t| t.into_owned()),
+        foreign,
+        form.error().map(|e| e.into_owned()),
+        form.description().map(|d| d.into_owned()),
+        dialog.constraints_error().map(str::to_owned),
+    ]
+    .into_iter()
+    .flatten()
+    {
+        text.push_str(&printable(&line));
+        text.push('\n');
+    }
+    text
+}
+
+// The text comes from the agent, its control characters could move the cursor or change the
+// settings of the terminal.
+fn printable(text: &str) -> String {
+    text.chars()
+        .filter(|c| *c == '\n' || !c.is_control())
+        .collect()
+}
+
+// Buttons of a CONFIRM or MESSAGE dialog, like the graphical dialog the last one is suggested.
+fn choices(dialog: &Dialog) -> Vec<(String, Input)> {
+    let form = dialog.form();
+    let mut choices = Vec::new();
+
+    match dialog.mode() {
+        Mode::Confirm { one_button: false } => {
+            choices.push((form.button_cancel().into_owned(), Input::Cancel));
+            if let Some(label) = form.button_not_ok() {
+                choices.push((label.into_owned(), Input::NotOk));
+            }
+            choices.push((form.button_yes().into_owned(), Input::Ok));
+        }
+        Mode::Confirm { one_button: true } => {
+            choices.push((form.button_yes().into_owned(), Input::Ok));
+        }
+        Mode::Message | Mode::Passphrase => {
+            choices.push((form.button_ok().into_owned(), Input::Ok));
+        }
+    }
+    choices
+}
+
+// The key picking a button, its mnemonic or else its first letter.
+fn shortcut(mnemonic: &Mnemonic) -> Option<char> {
+    mnemonic.key().or_else(|| mnemonic.text().chars().next())
+}
+
+// Removes the last character, which may take several bytes.
+fn pop_char(typed: &mut Vec<u8>) {
+    while let Some(b) = typed.pop() {
+        let is_continuation = b & 0b1100_0000 == 0b1000_0000;
+        if !is_continuation {
+            break;
+        }
+    }
+}
+
+/// Terminal switched to non-canonical mode without echo, its settings are restored once
+/// dropped or when zuul is killed.
+struct Terminal {
+    file: File,
+    saved: Box<Saved>,
+}
+
+/// Settings of a terminal before zuul changed them.
+struct Saved {
+    fd: libc::c_int,
+    termios: libc::termios,
+}
+
+// The settings of the terminal currently open, restored by the signal handler. Only one
+// terminal is open at a time.
+static OPEN_TERMINAL: AtomicPtr<Saved> = AtomicPtr::new(std::ptr::null_mut());
+
+impl Terminal {
+    fn open(path: &Path) -> std::io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+
+        let mut saved = MaybeUninit::<libc::termios>::uninit();
+        // SAFETY: `saved` is only read once tcgetattr initialized it.
+        let saved = unsafe {
+            if libc::tcgetattr(file.as_raw_fd(), saved.as_mut_ptr()) != 0 {
+                return Err(Error::last_os_error());
+            }
+            saved.assume_init()
+        };
+
+        // Keys are read one by one, Ctrl+C is read like any other key so the terminal is
+        // always restored.
+        let mut raw = saved;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        // SAFETY: `raw` is a valid termios.
+        if unsafe { libc::tcsetattr(file.as_raw_fd(), libc::TCSAFLUSH, &raw) } != 0 {
+            return Err(Error::last_os_error());
+        }
+
+        let saved = Box::new(Saved {
+            fd: file.as_raw_fd(),
+            termios: saved,
+        });
+        install_signal_handlers();
+        OPEN_TERMINAL.store(std::ptr::from_ref(&*saved).cast_mut(), Ordering::SeqCst);
+
+        Ok(Self { file, saved })
+    }
+
+    fn print(&mut self, text: &str) -> std::io::Result<()> {
+        self.file.write_all(text.as_bytes())?;
+        self.file.flush()
+    }
+
+    // True once a key can be read, false when the timeout expires first.
+    fn poll(&self, timeout: Option<Duration>) -> std::io::Result<bool> {
+        let mut fd = libc::pollfd {
+            fd: self.file.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout = timeout.map_or(-1, |t| t.as_millis().min(i32::MAX as u128) as i32);
+
+        loop {
+            // SAFETY: `fd` is a single valid pollfd.
+            match unsafe { libc::poll(&mut fd, 1, timeout) } {
+                -1 => match Error::last_os_error() {
+                    err if err.kind() == ErrorKind::Interrupted => continue,
+                    err => return Err(err),
+                },
+                0 => return Ok(false),
+                _ => return Ok(true),
+            }
+        }
+    }
+
+    // `None` at the end of the terminal.
+    fn read_byte(&mut self) -> std::io::Result<Option<u8>> {
+        let mut byte = [0u8; 1];
+        match self.file.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    // Discards what follows an Escape, a key that isn't part of a passphrase.
+    fn skip_sequence(&mut self) -> std::io::Result<()> {
+        let mut next = || -> std::io::Result<Option<u8>> {
+            match self.poll(Some(ESCAPE_DELAY))? {
+                true => self.read_byte(),
+                false => Ok(None),
+            }
+        };
+
+        match next()? {
+            // CSI, like the arrows: parameters up to a final byte between `@` and `~`.
+            Some(b'[') => while next()?.is_some_and(|b| !(0x40..=0x7e).contains(&b)) {},
+            // SS3, like F1 on some terminals: a single byte.
+            Some(b'O') => {
+                next()?;
+            }
+            // Alt with another key, which may take several bytes.
+            Some(b) => {
+                for _ in 1..b.leading_ones() {
+                    next()?;
+                }
+            }
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        // The signal handler must not see the settings once they are freed.
+        let saved = std::ptr::from_ref(&*self.saved).cast_mut();
+        let _ = OPEN_TERMINAL.compare_exchange(
+            saved,
+            std::ptr::null_mut(),
+            Ordering::SeqCst,
+            Ordering::SeqCst,
+        );
+        // SAFETY: `termios` was filled by tcgetattr.
+        unsafe { libc::tcsetattr(self.saved.fd, libc::TCSAFLUSH, &self.saved.termios) };
+    }
+}
+
+// Like Ctrl+C, the signals sent to stop zuul would leave the terminal without echo.
+fn install_signal_handlers() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        for signal in [libc::SIGHUP, libc::SIGINT, libc::SIGTERM] {
+            let handler = restore_and_raise as extern "C" fn(libc::c_int);
+            // SAFETY: the handler only calls async-signal-safe functions.
+            unsafe {
+                // A signal ignored by the agent stays ignored.
+                if libc::signal(signal, handler as libc::sighandler_t) == libc::SIG_IGN {
+                    libc::signal(signal, libc::SIG_IGN);
+                }
+            }
+        }
+    });
+}
+
+extern "C" fn restore_and_raise(signal: libc::c_int) {
+    restore_open_terminal();
+    // SAFETY: signal and raise are async-signal-safe, the default action stops zuul.
+    unsafe {
+        libc::signal(signal, libc::SIG_DFL);
+        libc::raise(signal);
+    }
+}
+
+fn restore_open_terminal() {
+    // SAFETY: the settings stay allocated as long as they are published and tcsetattr is
+    // async-signal-safe.
+    unsafe {
+        if let Some(saved) = OPEN_TERMINAL.load(Ordering::SeqCst).as_ref() {
+            libc::tcsetattr(saved.fd, libc::TCSAFLUSH, &saved.termios);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::secret_store::MemoryStore;
+    use assuan::{ErrorCode, Response};
+    use std::ffi::CStr;
+    use std::os::fd::FromRawFd;
+    use std::sync::{Mutex, MutexGuard};
+
+    // Pseudo terminal standing in for the user, the bytes written to it are typed. Like zuul,
+    // the tests only open one terminal at a time.
+    struct Pty {
+        master: File,
+        slave: PathBuf,
+        _serial: MutexGuard<'static, ()>,
+    }
+
+    impl Pty {
+        fn open() -> Pty {
+            static SERIAL: Mutex<()> = Mutex::new(());
+            let serial = SERIAL.lock().unwrap_or_else(|err| err.into_inner());
+
+            let mut name = [0 as libc::c_char; 64];
+            // SAFETY: the descriptor is checked before use and `name` is large enough for the
+            // path of the slave.
+            unsafe {
+                let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+                assert!(fd >= 0);
+                assert_eq!(0, libc::grantpt(fd));
+                assert_eq!(0, libc::unlockpt(fd));
+                assert_eq!(0, libc::ptsname_r(fd, name.as_mut_ptr(), name.len()));
+                Pty {
+                    master: File::from_raw_fd(fd),
+                    slave: PathBuf::from(CStr::from_ptr(name.as_ptr()).to_str().unwrap()),
+                    _serial: serial,
+                }
+            }
+        }
+
+        fn type_keys(&mut self, keys: &[u8]) {
+            self.master.write_all(keys).unwrap();
+        }
+    }
+
+    // A dialog displayed on the pseudo terminal, the keys can be typed once it's open.
+    fn tty_with(pty: &Pty, commands: &[&str], command: Command) -> Tty<MemoryStore> {
+        let mut tty = Tty {
+            session: Session::new(SystemClock, MemoryStore::default()),
+            default_tty: Some(pty.slave.clone()),
+            terminal: None,
+        };
+        for line in commands {
+            tty.session.command(Command::try_from(*line).unwrap());
+        }
+        let effects = tty.session.command(command);
+        assert_eq!(None, tty.perform(effects));
+        assert!(tty.terminal.is_some());
+        tty
+    }
+
+    fn sent(passphrase: &str) -> Vec<Effect> {
+        vec![
+            Effect::Hide,
+            Effect::Reply(Response::Data(passphrase.to_string())),
+            Effect::Reply(Response::Ok),
+        ]
+    }
+
+    fn dialog_with(commands: &[&str], command: Command) -> Session<SystemClock, MemoryStore> {
+        let mut session = Session::new(SystemClock, MemoryStore::default());
+        for line in commands {
+            session.command(Command::try_from(*line).unwrap());
+        }
+        session.command(command);
+        session
+    }
+
+    fn labelled(choices: &[(&str, Input)]) -> Vec<(String, Input)> {
+        choices
+            .iter()
+            .map(|(label, input)| (label.to_string(), input.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn confirm_choices() {
+        let commands = ["SETOK Yes", "SETCANCEL No"];
+        let session = dialog_with(&commands, Command::Confirm { one_button: false });
+        assert_eq!(
+            labelled(&[("No", Input::Cancel), ("Yes", Input::Ok)]),
+            choices(session.dialog().unwrap())
+        );
+
+        let commands = ["SETOK Yes", "SETCANCEL No", "SETNOTOK Later"];
+        let session = dialog_with(&commands, Command::Confirm { one_button: false });
+        assert_eq!(
+            labelled(&[
+                ("No", Input::Cancel),
+                ("Later", Input::NotOk),
+                ("Yes", Input::Ok)
+            ]),
+            choices(session.dialog().unwrap())
+        );
+    }
+
+    #[test]
+    fn one_button_confirm_choices() {
+        let commands = ["SETOK Close", "SETCANCEL No", "SETNOTOK Later"];
+        let session = dialog_with(&commands, Command::Confirm { one_button: true });
+        assert_eq!(
+            labelled(&[("Close", Input::Ok)]),
+            choices(session.dialog().unwrap())
+        );
+    }
+
+    #[test]
+    fn message_and_passphrase_choices() {
+        for command in [Command::Message, Command::GetPin] {
+            let session = dialog_with(&["SETOK Got it", "SETCANCEL No"], command);
+            assert_eq!(
+                labelled(&[("Got it", Input::Ok)]),
+                choices(session.dialog().unwrap())
+            );
+        }
+    }
+
+    #[test]
+    fn render_strips_control_characters() {
+        let session = dialog_with(
+            &[
+                "SETTITLE Unlock%07",
+                "SETDESC Key%0A%1B]0;owned%07%1B[2Jhere%0D",
+            ],
+            Command::GetPin,
+        );
+
+        assert_eq!(
+            "\nUnlock\nKey\n]0;owned[2Jhere\n",
+            render(session.dialog().unwrap())
+        );
+    }
+
+    fn echoes(terminal: &Terminal) -> bool {
+        let mut termios = MaybeUninit::<libc::termios>::uninit();
+        // SAFETY: `termios` is only read once tcgetattr initialized it.
+        unsafe {
+            assert_eq!(
+                0,
+                libc::tcgetattr(terminal.file.as_raw_fd(), termios.as_mut_ptr())
+            );
+            termios.assume_init().c_lflag & libc::ECHO != 0
+        }
+    }
+
+    #[test]
+    fn signal_handler_restores_the_terminal() {
+        let pty = Pty::open();
+        let terminal = Terminal::open(&pty.slave).unwrap();
+        assert!(!echoes(&terminal));
+
+        restore_open_terminal();
+        assert!(echoes(&terminal));
+
+        drop(terminal);
+        assert!(OPEN_TERMINAL.load(Ordering::SeqCst).is_null());
+    }
+
+    #[test]
+    fn escape_alone_cancels() {
+        let mut pty = Pty::open();
+        let mut tty = tty_with(&pty, &[], Command::GetPin);
+        pty.type_keys(b"12\x1b");
+
+        assert_eq!(
+            vec![
+                Effect::Hide,
+                Effect::Reply(Response::Err(ErrorCode::Canceled)),
+            ],
+            tty.ask_passphrase().unwrap()
+        );
+    }
+
+    #[test]
+    fn escape_sequences_are_ignored() {
+        let mut pty = Pty::open();
+        let mut tty = tty_with(&pty, &[], Command::GetPin);
+        // Up, F1, Alt+x, Alt+é and Ctrl+Right between the characters.
+        pty.type_keys("1\x1b[A2\x1bOP3\x1bx4\x1bé5\x1b[1;5C6\r".as_bytes());
+
+        assert_eq!(sent("123456"), tty.ask_passphrase().unwrap());
+    }
+
+    #[test]
+    fn passphrase_is_edited_before_it_is_sent() {
+        let mut pty = Pty::open();
+        let mut tty = tty_with(&pty, &[], Command::GetPin);
+        // Backspace removes a whole character and Ctrl+U the whole entry.
+        pty.type_keys("wrong\x15sé\x7fecret\r".as_bytes());
+
+        assert_eq!(sent("secret"), tty.ask_passphrase().unwrap());
+    }
+
+    #[test]
+    fn card_pin_refuses_letters() {
+        let mut pty = Pty::open();
+        let mut tty = tty_with(
+            &pty,
+            &["SETKEYINFO --D2760001240103040006123456780000"],
+            Command::GetPin,
+        );
+        pty.type_keys(b"1a2\r");
+
+        assert_eq!(sent("12"), tty.ask_passphrase().unwrap());
+    }
+
+    #[test]
+    fn guessed_pin_accepts_letters() {
+        let mut pty = Pty::open();
+        let mut tty = tty_with(&pty, &["SETPROMPT PIN"], Command::GetPin);
+        pty.type_keys(b"1a2\r");
+
+        assert_eq!(sent("1a2"), tty.ask_passphrase().unwrap());
+    }
+
+    #[test]
+    fn repeated_passphrase_is_sent() {
+        let mut pty = Pty::open();
+        let mut tty = tty_with(&pty, &["SETREPEAT"], Command::GetPin);
+        pty.type_keys(b"secret\rsecret\r");
+
+        assert_eq!(
+            vec![
+                Effect::Hide,
+                Effect::Reply(Response::Status("PIN_REPEATED".to_string())),
+                Effect::Reply(Response::Data("secret".to_string())),
+                Effect::Reply(Response::Ok),
+            ],
+            tty.ask_passphrase().unwrap()
+        );
+    }
+
+    #[test]
+    fn repetition_mismatch_asks_again() {
+        let mut pty = Pty::open();
+        let mut tty = tty_with(&pty, &["SETREPEAT"], Command::GetPin);
+        pty.type_keys(b"secret\rsecrte\r");
+
+        assert!(tty.ask_passphrase().unwrap().is_empty());
+        let dialog = tty.session.dialog().unwrap();
+        assert_eq!("", dialog.passphrase());
+        assert_eq!("", dialog.repeat());
+    }
+
+    #[test]
+    fn confirm_is_answered_with_a_mnemonic() {
+        let mut pty = Pty::open();
+        let commands = ["SETOK _Yes", "SETCANCEL _No"];
+        let mut tty = tty_with(&pty, &commands, Command::Confirm { one_button: false });
+        pty.type_keys(b"x n");
+
+        assert_eq!(
+            vec![
+                Effect::Hide,
+                Effect::Reply(Response::Err(ErrorCode::Canceled)),
+            ],
+            tty.prompt().unwrap()
+        );
+    }
+
+    #[test]
+    fn enter_picks_the_last_choice() {
+        let mut pty = Pty::open();
+        let commands = ["SETOK _Yes", "SETCANCEL _No"];
+        let mut tty = tty_with(&pty, &commands, Command::Confirm { one_button: false });
+        pty.type_keys(b"\r");
+
+        assert_eq!(
+            vec![Effect::Hide, Effect::Reply(Response::Ok)],
+            tty.prompt().unwrap()
+        );
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn ttyname_from_separate_argument() {
+        assert_eq!(
+            Some(PathBuf::from("/dev/pts/3")),
+            ttyname_arg(args(&["--display", ":0", "--ttyname", "/dev/pts/3"]))
+        );
+    }
+
+    #[test]
+    fn ttyname_from_joined_argument() {
+        assert_eq!(
+            Some(PathBuf::from("/dev/pts/3")),
+            ttyname_arg(args(&["--ttyname=/dev/pts/3"]))
+        );
+    }
+
+    #[test]
+    fn ttyname_missing() {
+        assert_eq!(None, ttyname_arg(args(&["--display", ":0"])));
+        assert_eq!(None, ttyname_arg(args(&["--ttyname"])));
+    }
+
+    #[test]
+    fn pop_removes_a_whole_character() {
+        let mut typed = "aé".as_bytes().to_vec();
+        pop_char(&mut typed);
+        assert_eq!(b"a", typed.as_slice());
+        pop_char(&mut typed);
+        assert!(typed.is_empty());
+        pop_char(&mut typed);
+        assert!(typed.is_empty());
+    }
+
+    #[test]
+    fn shortcut_is_the_mnemonic_or_the_first_letter() {
+        assert_eq!(Some('o'), shortcut(&Mnemonic::parse("C_ontinue")));
+        assert_eq!(Some('C'), shortcut(&Mnemonic::parse("Cancel")));
+        assert_eq!(None, shortcut(&Mnemonic::parse("")));
+    }
+}